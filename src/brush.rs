@@ -0,0 +1,246 @@
+use bevy::{input::mouse::MouseWheel, prelude::*};
+use sketch_assist::sketch::{Stroke, Tool};
+
+use crate::draw::{ctrl_pressed, CANVAS_HEIGHT, OFFSET};
+
+const MIN_RADIUS: f32 = 0.5;
+const MAX_RADIUS: f32 = 32.;
+
+// Selected with the number keys 1 to 6
+const PALETTE: [(&str, Color); 6] = [
+    ("black", Color::BLACK),
+    ("red", Color::RED),
+    ("green", Color::GREEN),
+    ("blue", Color::BLUE),
+    ("orange", Color::ORANGE),
+    ("purple", Color::PURPLE),
+];
+
+// Cycled with the O key
+const OPACITIES: [f32; 4] = [1.0, 0.75, 0.5, 0.25];
+
+// Stabilizer strengths cycled with the T key, starting with off
+const STABILIZER_LEVELS: [f32; 4] = [0.0, 0.5, 0.75, 0.9];
+
+// Style of the strokes drawn from now on
+pub struct Brush {
+    pub tool: Tool,
+    // In canvas pixels
+    pub radius: f32,
+    pub color: Color,
+    pub opacity: f32,
+    pub eraser_radius: f32,
+    // Strength of the input smoothing, 0 for none
+    pub stabilizer: f32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Brush {
+            tool: Tool::Pen,
+            radius: 2.,
+            color: Color::BLACK,
+            opacity: 1.,
+            eraser_radius: 8.,
+            stabilizer: 0.,
+        }
+    }
+}
+
+impl Brush {
+    // An empty stroke drawn with the selected tool
+    pub fn stroke(&self) -> Stroke {
+        match self.tool {
+            Tool::Pen => self.pen_stroke(),
+            Tool::Eraser => self.eraser_stroke(),
+        }
+    }
+
+    pub fn eraser_stroke(&self) -> Stroke {
+        Stroke::eraser(self.eraser_radius)
+    }
+
+    pub fn pen_stroke(&self) -> Stroke {
+        let color = [
+            (self.color.r() * 255.) as u8,
+            (self.color.g() * 255.) as u8,
+            (self.color.b() * 255.) as u8,
+            (self.opacity * 255.) as u8,
+        ];
+        Stroke::new(self.radius, color)
+    }
+}
+
+// Color swatch and description of the brush below the canvas
+pub struct BrushSwatch;
+pub struct BrushText;
+
+pub fn create_toolbar(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let swatch_size = OFFSET / 2.;
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(swatch_size), Val::Px(swatch_size)),
+                position: Rect {
+                    left: Val::Px(OFFSET),
+                    top: Val::Px(OFFSET + CANVAS_HEIGHT + OFFSET / 4.),
+                    ..Default::default()
+                },
+                position_type: PositionType::Absolute,
+                ..Default::default()
+            },
+            material: materials.add(Color::BLACK.into()),
+            ..Default::default()
+        })
+        .insert(BrushSwatch);
+
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position: Rect {
+                    left: Val::Px(OFFSET + swatch_size * 1.5),
+                    top: Val::Px(OFFSET + CANVAS_HEIGHT + OFFSET / 4.),
+                    ..Default::default()
+                },
+                position_type: PositionType::Absolute,
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSans.ttf"),
+                    font_size: 20.,
+                    color: Color::BLACK,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(BrushText);
+}
+
+// Mouse wheel or [ and ] change the size of the selected tool, 1 to 6 the
+// color and O the opacity. E switches between pen and eraser and T changes
+// the stabilizer strength.
+pub fn adjust_brush(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut mouse_wheel_events: EventReader<MouseWheel>,
+    mut brush: ResMut<Brush>,
+) {
+    let mut steps = 0.;
+    for event in mouse_wheel_events.iter() {
+        steps += event.y.signum();
+    }
+    if keyboard_input.just_pressed(KeyCode::RBracket) {
+        steps += 1.;
+    }
+    if keyboard_input.just_pressed(KeyCode::LBracket) {
+        steps -= 1.;
+    }
+    if steps != 0. {
+        let radius = match brush.tool {
+            Tool::Pen => &mut brush.radius,
+            Tool::Eraser => &mut brush.eraser_radius,
+        };
+        // Grow by 25% per step so that small brushes can be tuned finely
+        *radius = (*radius * 1.25_f32.powf(steps))
+            .max(MIN_RADIUS)
+            .min(MAX_RADIUS);
+    }
+
+    // E toggles the eraser, unless Ctrl is held for Ctrl+E (export)
+    if keyboard_input.just_pressed(KeyCode::E) && !ctrl_pressed(&keyboard_input) {
+        brush.tool = match brush.tool {
+            Tool::Pen => Tool::Eraser,
+            Tool::Eraser => Tool::Pen,
+        };
+    }
+
+    let keys = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+    ];
+    for (key, (_, color)) in keys.iter().zip(PALETTE.iter()) {
+        if keyboard_input.just_pressed(*key) {
+            brush.color = *color;
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::O) {
+        let current = OPACITIES
+            .iter()
+            .position(|&opacity| opacity == brush.opacity)
+            .unwrap_or(0);
+        brush.opacity = OPACITIES[(current + 1) % OPACITIES.len()];
+    }
+
+    if keyboard_input.just_pressed(KeyCode::T) {
+        let current = STABILIZER_LEVELS
+            .iter()
+            .position(|&level| level == brush.stabilizer)
+            .unwrap_or(0);
+        brush.stabilizer = STABILIZER_LEVELS[(current + 1) % STABILIZER_LEVELS.len()];
+    }
+}
+
+pub fn update_toolbar(
+    brush: Res<Brush>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    swatch: Query<&Handle<ColorMaterial>, With<BrushSwatch>>,
+    mut text: Query<&mut Text, With<BrushText>>,
+) {
+    if !brush.is_changed() {
+        return;
+    }
+
+    let (color, description) = match brush.tool {
+        Tool::Pen => {
+            let mut color = brush.color;
+            color.set_a(brush.opacity);
+
+            let name = PALETTE
+                .iter()
+                .find(|(_, color)| *color == brush.color)
+                .map_or("custom", |(name, _)| name);
+            let description = format!(
+                "{} {:.1}px  opacity {:.0}%  (wheel or [ ] size, 1-6 color, O opacity, E eraser)",
+                name,
+                brush.radius * 2.,
+                brush.opacity * 100.
+            );
+            (color, description)
+        }
+        Tool::Eraser => {
+            let description = format!(
+                "eraser {:.1}px  (wheel or [ ] size, E pen, or drag with the right button)",
+                brush.eraser_radius * 2.
+            );
+            (Color::WHITE, description)
+        }
+    };
+
+    for handle in swatch.iter() {
+        if let Some(material) = materials.get_mut(handle) {
+            material.color = color;
+        }
+    }
+
+    let stabilizer = if brush.stabilizer > 0. {
+        format!("stabilizer {:.0}%", brush.stabilizer * 100.)
+    } else {
+        "stabilizer off".to_string()
+    };
+
+    for mut text in text.iter_mut() {
+        text.sections[0].value = format!("{}  {} (T)", description, stabilizer);
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::Context;
+use image::{DynamicImage, GrayImage, RgbImage};
+use serde::Serialize;
+use std::{cmp::Ordering, io::Read, path::Path};
+use tract_onnx::prelude::*;
+
+use crate::{labels::Labels, preprocess::PreprocessConfig};
+
+pub type OnnxModel = SimplePlan<
+    TypedFact,
+    Box<dyn TypedOp>,
+    tract_onnx::prelude::Graph<TypedFact, Box<dyn TypedOp>>,
+>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prediction {
+    pub class: usize,
+    pub probability: f32,
+}
+
+// A prediction along with the name of its class
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LabeledPrediction {
+    pub class: usize,
+    pub label: String,
+    pub probability: f32,
+}
+
+pub struct SketchClassifier {
+    model: OnnxModel,
+    // model_version from the ONNX metadata, 0 if it was not set
+    version: i64,
+    labels: Labels,
+    preprocess: PreprocessConfig,
+}
+
+impl SketchClassifier {
+    // Load a model along with the labels and preprocessing files next to it,
+    // if there are any
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let mut file = std::fs::File::open(path)
+            .with_context(|| format!("could not open model {}", path.display()))?;
+        let mut classifier = Self::from_reader(&mut file)
+            .with_context(|| format!("could not load model {}", path.display()))?;
+
+        let labels_path = Labels::sidecar_path(path);
+        if labels_path.exists() {
+            let labels = Labels::load(&labels_path)
+                .with_context(|| format!("could not read labels {}", labels_path.display()))?;
+            classifier = classifier.with_labels(labels);
+        }
+
+        let preprocess_path = PreprocessConfig::sidecar_path(path);
+        if preprocess_path.exists() {
+            let preprocess = PreprocessConfig::load(&preprocess_path).with_context(|| {
+                format!("could not read preprocessing {}", preprocess_path.display())
+            })?;
+            classifier = classifier.with_preprocess(preprocess);
+        }
+
+        Ok(classifier)
+    }
+
+    pub fn from_reader<R: Read>(reader: &mut R) -> anyhow::Result<Self> {
+        let onnx = tract_onnx::onnx();
+        let proto = onnx
+            .proto_model_for_read(reader)
+            .context("not a valid ONNX model")?;
+        let model = onnx
+            .model_for_proto_model(&proto)
+            .context("not a valid ONNX model")?
+            .into_optimized()
+            .context("could not optimize the model")?
+            .into_runnable()
+            .context("could not build an execution plan")?;
+
+        Ok(SketchClassifier {
+            model,
+            version: proto.model_version,
+            labels: Labels::default(),
+            preprocess: PreprocessConfig::default(),
+        })
+    }
+
+    pub fn with_labels(mut self, labels: Labels) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    pub fn with_preprocess(mut self, preprocess: PreprocessConfig) -> Self {
+        self.preprocess = preprocess;
+        self
+    }
+
+    pub fn labels(&self) -> &Labels {
+        &self.labels
+    }
+
+    pub fn preprocess(&self) -> &PreprocessConfig {
+        &self.preprocess
+    }
+
+    pub fn version(&self) -> i64 {
+        self.version
+    }
+
+    // Returns every class ranked from the most to the least probable
+    pub fn classify(&self, img: &RgbImage) -> anyhow::Result<Vec<Prediction>> {
+        Ok(rank(&self.run(self.preprocess.apply(img))?))
+    }
+
+    // The `top` most probable classes with their names
+    pub fn classify_top(
+        &self,
+        img: &RgbImage,
+        top: usize,
+    ) -> anyhow::Result<Vec<LabeledPrediction>> {
+        Ok(self
+            .classify(img)?
+            .into_iter()
+            .take(top)
+            .map(|prediction| LabeledPrediction {
+                class: prediction.class,
+                label: self.labels.name(prediction.class),
+                probability: prediction.probability,
+            })
+            .collect())
+    }
+
+    pub fn classify_gray(&self, img: &GrayImage) -> anyhow::Result<Vec<Prediction>> {
+        self.classify(&DynamicImage::ImageLuma8(img.clone()).to_rgb8())
+    }
+
+    // Run the model on an already preprocessed input and return the raw score
+    // of each class
+    pub fn run(&self, input: Tensor) -> anyhow::Result<Vec<f32>> {
+        let result = self.model.run(tvec!(input))?;
+
+        Ok(result[0].to_array_view::<f32>()?.iter().cloned().collect())
+    }
+}
+
+pub fn softmax(scores: &[f32]) -> Vec<f32> {
+    // Subtract the max score so that exp() can not overflow
+    let max = scores.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let exps: Vec<f32> = scores.iter().map(|s| (s - max).exp()).collect();
+    let sum: f32 = exps.iter().sum();
+
+    exps.iter().map(|e| e / sum).collect()
+}
+
+// Convert raw scores into predictions sorted by descending probability
+pub fn rank(scores: &[f32]) -> Vec<Prediction> {
+    let mut predictions: Vec<Prediction> = softmax(scores)
+        .into_iter()
+        .enumerate()
+        .map(|(class, probability)| Prediction { class, probability })
+        .collect();
+
+    predictions.sort_by(most_probable_first);
+    predictions
+}
+
+// NaN, e.g. from a broken model, is ranked last rather than panicking
+fn most_probable_first(a: &Prediction, b: &Prediction) -> Ordering {
+    match (a.probability.is_nan(), b.probability.is_nan()) {
+        (false, false) => b.probability.partial_cmp(&a.probability).unwrap(),
+        (a_nan, b_nan) => a_nan.cmp(&b_nan),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rank_sorts_by_probability() {
+        let predictions = rank(&[1.0, 3.0, 2.0]);
+        let classes: Vec<usize> = predictions.iter().map(|p| p.class).collect();
+        assert_eq!(classes, vec![1, 2, 0]);
+
+        let sum: f32 = predictions.iter().map(|p| p.probability).sum();
+        assert!((sum - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rank_puts_nan_last() {
+        let predictions = rank(&[1.0, f32::NAN, 2.0]);
+        assert_eq!(predictions.len(), 3);
+        assert!(predictions.iter().all(|p| p.probability.is_nan()));
+
+        let mut predictions = rank(&[1.0, 2.0]);
+        predictions.insert(
+            0,
+            Prediction {
+                class: 2,
+                probability: f32::NAN,
+            },
+        );
+        predictions.sort_by(most_probable_first);
+        assert_eq!(predictions.last().unwrap().class, 2);
+    }
+
+    #[test]
+    fn softmax_does_not_overflow() {
+        let probabilities = softmax(&[1000.0, 1000.0, -1000.0]);
+        assert!((probabilities[0] - 0.5).abs() < 1e-6);
+        assert!((probabilities[1] - 0.5).abs() < 1e-6);
+        assert_eq!(probabilities[2], 0.0);
+    }
+}
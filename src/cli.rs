@@ -0,0 +1,496 @@
+// Command line tools that run the classifier without opening a window. The
+// app starts as usual unless the first argument names one of them.
+//
+//     sketch_assist [--models DIR] [FILE]
+//     sketch_assist classify [--model PATH] [--top K] [--json] FILES...
+//     sketch_assist evaluate [--model PATH] [--limit N] [--csv PATH] INPUTS...
+//     sketch_assist serve [--model PATH] [--top K] [--port PORT]
+
+use anyhow::{bail, Context};
+use image::{Rgb, RgbImage};
+use serde::Serialize;
+use sketch_assist::{
+    classifier::{LabeledPrediction, SketchClassifier},
+    evaluation::ConfusionMatrix,
+    quickdraw::{self, Drawing},
+    raster,
+};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::draw::{CANVAS_HEIGHT, CANVAS_WIDTH, TEXTURE_SIZE};
+use crate::model::MODEL_PATH;
+use crate::server;
+
+pub const USAGE: &str = "\
+Usage: sketch_assist [--models DIR] [FILE]
+       sketch_assist classify [OPTIONS] FILES...
+       sketch_assist evaluate [OPTIONS] INPUTS...
+       sketch_assist serve [OPTIONS]
+
+Without a command the app opens FILE, an image or a Quick Draw .ndjson file,
+on the canvas. --models DIR lets the app switch between the .onnx models of
+DIR with the M key. Models are only reloaded when they change if DIR is in
+the assets folder.
+
+classify: Classify PNG or JPEG images of sketches and print the most likely
+labels.
+
+evaluate: Measure the accuracy of the model on labelled samples and print
+per-class precision and recall and a confusion matrix. An input is either
+  - a folder with a subfolder of PNG or JPEG images for every class,
+  - a Quick Draw .ndjson file, labelled by the word of each drawing, or
+  - a Quick Draw numpy_bitmap .npy file, labelled by its file name.
+Folders may contain .ndjson and .npy files as well.
+
+serve: Classify sketches over HTTP on localhost.
+  GET  /health    Status of the server and information about the model
+  POST /classify  Classify the image in the body, or a Quick Draw drawing
+                  if the body is JSON. Takes an optional ?top=K.
+
+Options:
+    --model PATH  ONNX model, with optional .labels and .preprocess.json
+                  files next to it (default: assets/cnn_sketch_3class.onnx)
+    --help        Print this message
+
+classify and serve:
+    --top K       Number of labels to return per file (default: 5)
+
+classify:
+    --json        Print one JSON object per file instead of text
+
+evaluate:
+    --limit N     Samples to take at most from each class folder or file
+    --csv PATH    Also write the confusion matrix to a CSV file
+
+serve:
+    --port PORT   Port to listen on (default: 8080)";
+
+// Options of each command besides --model and --help
+const COMMAND_OPTIONS: [(&str, &[&str]); 3] = [
+    ("classify", &["--top", "--json"]),
+    ("evaluate", &["--limit", "--csv"]),
+    ("serve", &["--top", "--port"]),
+];
+
+// Run the command in `args` (without the program name), returning the exit
+// code, or None to start the app
+pub fn run(args: &[String]) -> Option<i32> {
+    let result = match args.first().map(String::as_str) {
+        Some("classify") => classify(&args[1..]),
+        Some("evaluate") => evaluate(&args[1..]),
+        Some("serve") => serve(&args[1..]),
+        _ => return None,
+    };
+
+    Some(match result {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {:#}", err);
+            2
+        }
+    })
+}
+
+// Arguments of the app itself
+#[derive(Clone, Default)]
+pub struct AppArgs {
+    // Folder of models to switch between
+    pub models: Option<PathBuf>,
+    // File to open on the canvas
+    pub file: Option<PathBuf>,
+    pub help: bool,
+}
+
+impl AppArgs {
+    pub fn parse(args: &[String]) -> anyhow::Result<Self> {
+        let mut app_args = AppArgs::default();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--models" => {
+                    let dir = args.next().context("--models needs a value")?;
+                    app_args.models = Some(PathBuf::from(dir));
+                }
+                "--help" | "-h" => app_args.help = true,
+                flag if flag.starts_with("--") => bail!("unknown option {}\n\n{}", flag, USAGE),
+                file if app_args.file.is_none() => app_args.file = Some(PathBuf::from(file)),
+                _ => bail!("only one file can be opened\n\n{}", USAGE),
+            }
+        }
+
+        Ok(app_args)
+    }
+}
+
+struct Options {
+    model: PathBuf,
+    top: usize,
+    json: bool,
+    limit: Option<usize>,
+    csv: Option<PathBuf>,
+    port: u16,
+    help: bool,
+    files: Vec<PathBuf>,
+}
+
+impl Options {
+    fn parse(command: &str, args: &[String]) -> anyhow::Result<Self> {
+        let takes = COMMAND_OPTIONS
+            .iter()
+            .find(|(name, _)| *name == command)
+            .map_or(&[][..], |&(_, options)| options);
+
+        let mut options = Options {
+            model: Path::new("assets").join(MODEL_PATH),
+            top: 5,
+            json: false,
+            limit: None,
+            csv: None,
+            port: 8080,
+            help: false,
+            files: Vec::new(),
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let of_other_command = COMMAND_OPTIONS
+                .iter()
+                .any(|(_, options)| options.contains(&arg.as_str()));
+            if of_other_command && !takes.contains(&arg.as_str()) {
+                bail!("{} does not take {}\n\n{}", command, arg, USAGE);
+            }
+
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("{} needs a value", arg))
+            };
+            match arg.as_str() {
+                "--model" => options.model = PathBuf::from(value()?),
+                "--top" => {
+                    options.top = value()?.parse().context("--top needs a number")?;
+                }
+                "--json" => options.json = true,
+                "--limit" => {
+                    options.limit = Some(value()?.parse().context("--limit needs a number")?);
+                }
+                "--csv" => options.csv = Some(PathBuf::from(value()?)),
+                "--port" => options.port = value()?.parse().context("--port needs a number")?,
+                "--help" | "-h" => options.help = true,
+                flag if flag.starts_with("--") => bail!("unknown option {}", flag),
+                file => options.files.push(PathBuf::from(file)),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+#[derive(Serialize)]
+struct FileResult<'a> {
+    file: &'a Path,
+    predictions: Vec<LabeledPrediction>,
+}
+
+// Returns 1 if any of the files could not be classified
+fn classify(args: &[String]) -> anyhow::Result<i32> {
+    let options = Options::parse("classify", args)?;
+    if options.help {
+        println!("{}", USAGE);
+        return Ok(0);
+    }
+    if options.files.is_empty() {
+        bail!("no files to classify\n\n{}", USAGE);
+    }
+
+    let classifier = SketchClassifier::load(&options.model)?;
+
+    let mut failed = false;
+    for file in options.files.iter() {
+        let predictions = match classify_file(&classifier, file, options.top) {
+            Ok(predictions) => predictions,
+            Err(err) => {
+                eprintln!("error: {}: {:#}", file.display(), err);
+                failed = true;
+                continue;
+            }
+        };
+
+        if options.json {
+            let result = FileResult { file, predictions };
+            println!("{}", serde_json::to_string(&result)?);
+        } else {
+            println!("{}", file.display());
+            for prediction in predictions {
+                println!(
+                    "  {:<20} {:6.2}%",
+                    prediction.label,
+                    prediction.probability * 100.
+                );
+            }
+        }
+    }
+
+    Ok(if failed { 1 } else { 0 })
+}
+
+// Transparent parts of the image are treated as the white canvas
+fn classify_file(
+    classifier: &SketchClassifier,
+    path: &Path,
+    top: usize,
+) -> anyhow::Result<Vec<LabeledPrediction>> {
+    let img = image::open(path).context("could not read the image")?;
+    classifier.classify_top(&raster::flatten(&img.to_rgba8()), top)
+}
+
+fn serve(args: &[String]) -> anyhow::Result<i32> {
+    let options = Options::parse("serve", args)?;
+    if options.help {
+        println!("{}", USAGE);
+        return Ok(0);
+    }
+    if !options.files.is_empty() {
+        bail!("serve takes no files\n\n{}", USAGE);
+    }
+
+    let classifier = SketchClassifier::load(&options.model)?;
+    server::serve(&classifier, &options.model, options.port, options.top)?;
+    Ok(0)
+}
+
+// Prints the report and returns 1 if some samples could not be read
+fn evaluate(args: &[String]) -> anyhow::Result<i32> {
+    let options = Options::parse("evaluate", args)?;
+    if options.help {
+        println!("{}", USAGE);
+        return Ok(0);
+    }
+    if options.files.is_empty() {
+        bail!("nothing to evaluate\n\n{}", USAGE);
+    }
+
+    let classifier = SketchClassifier::load(&options.model)?;
+    let labels = classifier.labels();
+    if labels.is_empty() {
+        bail!("the model has no .labels file to match class names against");
+    }
+
+    let mut matrix = ConfusionMatrix::new(labels.len());
+    // Samples of classes the model does not know, by label
+    let mut unknown: BTreeMap<String, usize> = BTreeMap::new();
+    let mut failed = false;
+
+    for input in options.files.iter() {
+        let mut sample = |label: &str, img: anyhow::Result<RgbImage>| {
+            let truth = match labels.find(label) {
+                Some(truth) => truth,
+                None => {
+                    *unknown.entry(label.to_string()).or_default() += 1;
+                    return;
+                }
+            };
+
+            let predictions = img.and_then(|img| classifier.classify(&img));
+            match predictions.as_ref().map(|predictions| predictions.first()) {
+                Ok(Some(top)) => matrix.add(truth, top.class),
+                Ok(None) => {}
+                Err(err) => {
+                    eprintln!("error: {}: {:#}", label, err);
+                    failed = true;
+                }
+            }
+        };
+
+        if let Err(err) = for_each_sample(input, options.limit, &mut sample) {
+            eprintln!("error: {}: {:#}", input.display(), err);
+            failed = true;
+        }
+    }
+
+    for (label, count) in unknown.iter() {
+        eprintln!(
+            "warning: skipped {} samples of {}, which the model has no class for",
+            count, label
+        );
+    }
+
+    print!("{}", matrix.report(labels));
+
+    if let Some(csv) = options.csv.as_ref() {
+        std::fs::write(csv, matrix.to_csv(labels))
+            .with_context(|| format!("could not write {}", csv.display()))?;
+    }
+
+    Ok(if failed { 1 } else { 0 })
+}
+
+// Hand every labelled sample of `input` to `sample`
+fn for_each_sample(
+    input: &Path,
+    limit: Option<usize>,
+    sample: &mut dyn FnMut(&str, anyhow::Result<RgbImage>),
+) -> anyhow::Result<()> {
+    let limit = limit.unwrap_or(usize::MAX);
+    let stem = input
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    if input.is_dir() {
+        for entry in sorted_entries(input)? {
+            if entry.is_dir() {
+                let label = entry.file_name().unwrap().to_string_lossy().into_owned();
+                let images = sorted_entries(&entry)?
+                    .into_iter()
+                    .filter(|path| is_image(path))
+                    .take(limit);
+                for path in images {
+                    let img = image::open(&path)
+                        .with_context(|| format!("could not read {}", path.display()))
+                        .map(|img| raster::flatten(&img.to_rgba8()));
+                    sample(&label, img);
+                }
+            } else if is_dataset_file(&entry) {
+                for_each_sample(&entry, Some(limit), sample)?;
+            } else if is_image(&entry) {
+                eprintln!(
+                    "warning: skipped {}, images need to be in a folder named after their class",
+                    entry.display()
+                );
+            } else {
+                eprintln!("warning: skipped {}", entry.display());
+            }
+        }
+        return Ok(());
+    }
+
+    match lowercase_extension(input).as_deref() {
+        Some("ndjson") => {
+            let text = std::fs::read_to_string(input)?;
+            for drawing in Drawing::parse_ndjson(&text)?.iter().take(limit) {
+                let label = if drawing.word.is_empty() {
+                    &stem
+                } else {
+                    &drawing.word
+                };
+                let img = drawing.render((CANVAS_WIDTH, CANVAS_HEIGHT), TEXTURE_SIZE, TEXTURE_SIZE);
+                sample(label, Ok(img));
+            }
+        }
+        Some("npy") => {
+            let bitmaps = quickdraw::parse_npy_bitmaps(&std::fs::read(input)?)?;
+            for bitmap in bitmaps.iter().take(limit) {
+                // Black ink on white like the canvas
+                let img = RgbImage::from_fn(bitmap.width(), bitmap.height(), |x, y| {
+                    let value = 255 - bitmap[(x, y)].0[0];
+                    Rgb([value, value, value])
+                });
+                sample(&stem, Ok(img));
+            }
+        }
+        _ => bail!("expected a folder, an .ndjson or an .npy file"),
+    }
+
+    Ok(())
+}
+
+fn sorted_entries(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+fn is_image(path: &Path) -> bool {
+    matches!(
+        lowercase_extension(path).as_deref(),
+        Some("png") | Some("jpg") | Some("jpeg")
+    )
+}
+
+// Quick Draw files that can be evaluated on their own
+fn is_dataset_file(path: &Path) -> bool {
+    matches!(
+        lowercase_extension(path).as_deref(),
+        Some("ndjson") | Some("npy")
+    )
+}
+
+fn lowercase_extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn options() {
+        let options = Options::parse(
+            "classify",
+            &args(&[
+                "--top", "3", "--json", "a.png", "--model", "m.onnx", "b.png",
+            ]),
+        )
+        .unwrap();
+        assert_eq!(options.top, 3);
+        assert!(options.json);
+        assert!(!options.help);
+        assert_eq!(options.model, PathBuf::from("m.onnx"));
+        assert_eq!(
+            options.files,
+            vec![PathBuf::from("a.png"), PathBuf::from("b.png")]
+        );
+
+        let options =
+            Options::parse("evaluate", &args(&["--limit", "10", "--csv", "m.csv"])).unwrap();
+        assert_eq!(options.limit, Some(10));
+        assert_eq!(options.csv, Some(PathBuf::from("m.csv")));
+        assert_eq!(options.model, Path::new("assets").join(MODEL_PATH));
+
+        let options = Options::parse("serve", &args(&["--port", "9000", "--top", "1"])).unwrap();
+        assert_eq!(options.port, 9000);
+        assert!(Options::parse("serve", &args(&["--help"])).unwrap().help);
+    }
+
+    #[test]
+    fn options_of_other_commands_are_rejected() {
+        let err = Options::parse("classify", &args(&["--port", "1"]))
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with("classify does not take --port"));
+        assert!(Options::parse("evaluate", &args(&["--json"])).is_err());
+        assert!(Options::parse("serve", &args(&["--csv", "x"])).is_err());
+        assert!(Options::parse("serve", &args(&["--limit", "1"])).is_err());
+    }
+
+    #[test]
+    fn invalid_options() {
+        assert!(Options::parse("classify", &args(&["--verbose"])).is_err());
+        assert!(Options::parse("classify", &args(&["--top"])).is_err());
+        assert!(Options::parse("classify", &args(&["--top", "many"])).is_err());
+    }
+
+    #[test]
+    fn app_args() {
+        let app_args = AppArgs::parse(&args(&["--models", "models", "rabbit.png"])).unwrap();
+        assert_eq!(app_args.models, Some(PathBuf::from("models")));
+        assert_eq!(app_args.file, Some(PathBuf::from("rabbit.png")));
+        assert!(!app_args.help);
+
+        assert!(AppArgs::parse(&args(&["-h"])).unwrap().help);
+        assert!(AppArgs::parse(&args(&["--help"])).unwrap().help);
+        assert!(AppArgs::parse(&args(&["--models"])).is_err());
+        assert!(AppArgs::parse(&args(&["--top", "3"])).is_err());
+        assert!(AppArgs::parse(&args(&["a.png", "b.png"])).is_err());
+    }
+}
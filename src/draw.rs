@@ -1,19 +1,42 @@
 use bevy::{
     input::{
         mouse::{MouseButtonInput, MouseMotion, MouseWheel},
+        touch::{TouchInput, TouchPhase},
         ElementState,
     },
     prelude::*,
     window::CursorMoved,
 };
 
+use image::{RgbImage, RgbaImage};
+use sketch_assist::{
+    raster::{self, StrokeRasterizer},
+    sketch::{Sketch, Stroke},
+    stabilizer::Stabilizer,
+};
+
+use crate::brush::Brush;
+
 pub struct Canvas;
 
 pub struct TestCanvas;
 
+pub struct ResultPanel;
+
+// Reference image of the predicted class, numbered from 1 like the asset files
+pub struct GalleryImage(pub usize);
+
 pub enum ImageEvent {
-    DrawPos(Vec2),
+    // Paint a segment of the current stroke, in sketch coordinates. Both ends
+    // are equal for the first point of a stroke.
+    Segment(Vec2, Vec2),
     Clear,
+    // Repaint the whole canvas from the strokes in `Sketch`
+    Redraw,
+    // The mouse button was released after drawing a stroke
+    StrokeEnd,
+    // Show an image under the strokes, fitted to the canvas
+    Background(RgbaImage),
 }
 
 pub const WINDOW_WIDTH: f32 = 1350.;
@@ -24,23 +47,53 @@ pub const OFFSET: f32 = WINDOW_HEIGHT / 14.;
 pub const CANVAS_WIDTH: f32 = (WINDOW_WIDTH - OFFSET * 3.0) / 2.0;
 pub const CANVAS_HEIGHT: f32 = WINDOW_HEIGHT - OFFSET * 2.0;
 
+// Size of empty.png, the texture the canvas is drawn on
+pub const TEXTURE_SIZE: u32 = 400;
+
 pub fn clear_canvas(
     keyboard_input: Res<Input<KeyCode>>,
-    mut commands: Commands,
-    mut materials: ResMut<Assets<ColorMaterial>>,
     mut image_events: EventWriter<ImageEvent>,
+    mut sketch: ResMut<Sketch>,
+    mut gallery: Query<&mut Visible, With<GalleryImage>>,
 ) {
     if keyboard_input.just_pressed(KeyCode::C) {
-        clear_inference(
-            &mut commands,
-            &mut materials,
-            WINDOW_WIDTH / 2.0 - CANVAS_WIDTH / 2.0 - OFFSET,
-        );
+        clear_inference(&mut gallery);
 
+        sketch.clear();
         image_events.send(ImageEvent::Clear);
     }
 }
 
+// Either Ctrl key
+pub fn ctrl_pressed(keyboard_input: &Input<KeyCode>) -> bool {
+    keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl)
+}
+
+// Ctrl+Z takes back the last stroke, Ctrl+Shift+Z or Ctrl+Y restores it
+pub fn undo_redo(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut image_events: EventWriter<ImageEvent>,
+    mut sketch: ResMut<Sketch>,
+) {
+    let ctrl = ctrl_pressed(&keyboard_input);
+    let shift = keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+    if !ctrl {
+        return;
+    }
+
+    let changed = if keyboard_input.just_pressed(KeyCode::Z) && !shift {
+        sketch.undo()
+    } else if keyboard_input.just_pressed(KeyCode::Z) || keyboard_input.just_pressed(KeyCode::Y) {
+        sketch.redo()
+    } else {
+        false
+    };
+
+    if changed {
+        image_events.send(ImageEvent::Redraw);
+    }
+}
+
 pub fn create_canvas(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
@@ -50,71 +103,7 @@ pub fn create_canvas(
 
     create_canvas_(&mut commands, &mut materials, &asset_server);
 
-    // Setup images on the right canvas
-
-    return;
-
-    let texture1 = asset_server.load("axe1.png");
-    let texture2 = asset_server.load("axe2.png");
-    let texture3 = asset_server.load("axe3.png");
-    let texture4 = asset_server.load("axe4.png");
-
-    // Upper left
-    commands.spawn_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(CANVAS_WIDTH / 2., CANVAS_HEIGHT / 2.)),
-        material: materials.add(texture1.into()),
-        transform: Transform {
-            translation: Vec3::new(
-                WINDOW_WIDTH / 2. - OFFSET - CANVAS_WIDTH / 2. - CANVAS_WIDTH / 4.,
-                WINDOW_HEIGHT / 2. - OFFSET - CANVAS_HEIGHT / 4.,
-                0.,
-            ),
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-    // Upper right
-    commands.spawn_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(CANVAS_WIDTH / 2., CANVAS_HEIGHT / 2.)),
-        material: materials.add(texture2.into()),
-        transform: Transform {
-            translation: Vec3::new(
-                WINDOW_WIDTH / 2. - OFFSET - CANVAS_WIDTH / 4.,
-                WINDOW_HEIGHT / 2. - OFFSET - CANVAS_HEIGHT / 4.,
-                0.,
-            ),
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-    // Lower left
-    commands.spawn_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(CANVAS_WIDTH / 2., CANVAS_HEIGHT / 2.)),
-        material: materials.add(texture3.into()),
-        transform: Transform {
-            translation: Vec3::new(
-                WINDOW_WIDTH / 2. - OFFSET - CANVAS_WIDTH / 2. - CANVAS_WIDTH / 4.,
-                -(WINDOW_HEIGHT / 2. - OFFSET - CANVAS_HEIGHT / 4.),
-                0.,
-            ),
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-    // Lower right
-    commands.spawn_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(CANVAS_WIDTH / 2., CANVAS_HEIGHT / 2.)),
-        material: materials.add(texture4.into()),
-        transform: Transform {
-            translation: Vec3::new(
-                WINDOW_WIDTH / 2. - OFFSET - CANVAS_WIDTH / 4.,
-                -(WINDOW_HEIGHT / 2. - OFFSET - CANVAS_HEIGHT / 4.),
-                0.,
-            ),
-            ..Default::default()
-        },
-        ..Default::default()
-    });
+    create_result_panel(&mut commands, &mut materials);
 }
 
 fn create_canvas_(
@@ -150,104 +139,254 @@ fn create_canvas_(
             ..Default::default()
         })
         .insert(TestCanvas);
-
-    clear_inference(
-        commands,
-        materials,
-        WINDOW_WIDTH / 2.0 - CANVAS_WIDTH / 2.0 - OFFSET,
-    );
 }
 
-// Area to show images on right side
-fn clear_inference(
-    commands: &mut Commands,
-    materials: &mut ResMut<Assets<ColorMaterial>>,
-    x_offset: f32,
-) {
-    commands.spawn_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(CANVAS_WIDTH, CANVAS_HEIGHT)),
-        material: materials.add(Color::WHITE.into()),
-        transform: Transform {
-            translation: Vec3::new(x_offset, 0., 0.),
+// Area to show images on right side. The reference images of the predicted
+// class are shown in a 2x2 grid of sprites whose materials are updated in place.
+fn create_result_panel(commands: &mut Commands, materials: &mut ResMut<Assets<ColorMaterial>>) {
+    // Upper left, upper right, lower left, lower right
+    let corners = [(-1., 1.), (1., 1.), (-1., -1.), (1., -1.)];
+
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(CANVAS_WIDTH, CANVAS_HEIGHT)),
+            material: materials.add(Color::WHITE.into()),
+            transform: Transform {
+                translation: Vec3::new(WINDOW_WIDTH / 2.0 - CANVAS_WIDTH / 2.0 - OFFSET, 0., 0.),
+                ..Default::default()
+            },
             ..Default::default()
-        },
-        ..Default::default()
-    });
+        })
+        .insert(ResultPanel)
+        .with_children(|parent| {
+            for (i, (x, y)) in corners.iter().enumerate() {
+                parent
+                    .spawn_bundle(SpriteBundle {
+                        sprite: Sprite::new(Vec2::new(CANVAS_WIDTH / 2., CANVAS_HEIGHT / 2.)),
+                        material: materials.add(ColorMaterial::default()),
+                        // Relative to the panel, in front of it
+                        transform: Transform {
+                            translation: Vec3::new(
+                                x * CANVAS_WIDTH / 4.,
+                                y * CANVAS_HEIGHT / 4.,
+                                1.,
+                            ),
+                            ..Default::default()
+                        },
+                        visible: Visible {
+                            is_visible: false,
+                            is_transparent: true,
+                        },
+                        ..Default::default()
+                    })
+                    .insert(GalleryImage(i + 1));
+            }
+        });
+}
+
+pub fn clear_inference(gallery: &mut Query<&mut Visible, With<GalleryImage>>) {
+    for mut visible in gallery.iter_mut() {
+        visible.is_visible = false;
+    }
 }
 
 pub fn mouse_draw(
     mut cursor_moved_events: EventReader<CursorMoved>,
     mut mouse_button_input_events: EventReader<MouseButtonInput>,
+    mouse_button_input: Res<Input<MouseButton>>,
     mut image_events: EventWriter<ImageEvent>,
-    mut last_mouse_position: Local<Option<Vec2>>,
+    mut stabilizer: Local<Stabilizer>,
+    mut sketch: ResMut<Sketch>,
+    brush: Res<Brush>,
+    time: Res<Time>,
     drawable: Query<(&Interaction, &GlobalTransform, &Style), With<Canvas>>,
 ) {
     for (interaction, transform, style) in drawable.iter() {
         if let Interaction::Hovered = interaction {
             // println!("Hovered");
         }
-        if let Interaction::Clicked = interaction {
+        // UI interaction only tracks the left button, so erasing with the
+        // right button only needs the cursor to be over the canvas
+        let erasing = mouse_button_input.pressed(MouseButton::Right);
+        if *interaction == Interaction::Clicked || (*interaction == Interaction::Hovered && erasing)
+        {
             // println!("Clicked");
             // dbg!(style);
             // dbg!(transform);
 
-            let width = if let Val::Px(x) = style.size.width {
-                x
-            } else {
-                0.
-            };
-            let height = if let Val::Px(x) = style.size.height {
-                x
-            } else {
-                0.
-            };
-
             for event in cursor_moved_events.iter() {
                 // info!("{:?}", event.position);
 
-                if let Some(last_mouse_position) = *last_mouse_position {
-                    // dbg!(last_mouse_position);
-                    // dbg!(last_mouse_position.distance(event.position));
+                if !sketch.is_drawing() {
+                    let stroke = if erasing {
+                        brush.eraser_stroke()
+                    } else {
+                        brush.stroke()
+                    };
+                    begin_stroke(&mut sketch, &mut stabilizer, stroke, brush.stabilizer);
+                }
 
-                    let steps =
-                        (last_mouse_position.distance(event.position) as u32 / 400 + 1) * 10;
+                let raw = to_sketch(event.position, transform, style);
+                let (x, y) = stabilizer.push(raw.x, raw.y);
+                add_point(&mut sketch, &mut image_events, Vec2::new(x, y), &time);
+            }
+        }
+    }
 
-                    for i in 0..steps {
-                        let lerped =
-                            last_mouse_position.lerp(event.position, i as f32 / steps as f32);
-                        let x = lerped.x - transform.translation.x + width / 2.;
-                        let y = lerped.y - transform.translation.y + height / 2.;
+    for event in mouse_button_input_events.iter() {
+        // info!("mouse_button_input_events: {:?}", event);
 
-                        // let y = 400 as f32 - y;
+        if event.state == ElementState::Released {
+            end_stroke(&mut sketch, &mut stabilizer, &mut image_events, &time);
+        }
+    }
+}
 
-                        // println!("{}, {}", x, y);
+// Fingers on the screen and the one drawing, if any
+#[derive(Default)]
+pub struct Touches {
+    down: Vec<u64>,
+    finger: Option<u64>,
+}
 
-                        image_events.send(ImageEvent::DrawPos(Vec2::new(x, y)));
+// Draw with a finger. A touch that starts while another one is down, like a
+// palm resting on the screen or the second finger of a pinch, makes it a
+// gesture: the stroke in progress is taken back and nothing is drawn until
+// all fingers are lifted.
+pub fn touch_draw(
+    mut touch_events: EventReader<TouchInput>,
+    mut image_events: EventWriter<ImageEvent>,
+    mut touches: Local<Touches>,
+    mut stabilizer: Local<Stabilizer>,
+    mut sketch: ResMut<Sketch>,
+    brush: Res<Brush>,
+    time: Res<Time>,
+    windows: Res<Windows>,
+    drawable: Query<(&GlobalTransform, &Style), With<Canvas>>,
+) {
+    let window_height = match windows.get_primary() {
+        Some(window) => window.height(),
+        None => return,
+    };
+    let (transform, style) = match drawable.iter().next() {
+        Some(canvas) => canvas,
+        None => return,
+    };
+
+    for event in touch_events.iter() {
+        // Unlike cursor positions, touch positions have y pointing down
+        let position = Vec2::new(event.position.x, window_height - event.position.y);
+        let pos = to_sketch(position, transform, style);
+
+        match event.phase {
+            TouchPhase::Started => {
+                touches.down.push(event.id);
+                if touches.down.len() > 1 {
+                    if touches.finger.take().is_some() && sketch.cancel_stroke() {
+                        stabilizer.reset();
+                        image_events.send(ImageEvent::Redraw);
                     }
-                } else {
-                    let x = event.position.x - transform.translation.x + width / 2.;
-                    let y = event.position.y - transform.translation.y + height / 2.;
-                    image_events.send(ImageEvent::DrawPos(Vec2::new(x, y)));
+                    continue;
+                }
+
+                let on_canvas =
+                    (0. ..CANVAS_WIDTH).contains(&pos.x) && (0. ..CANVAS_HEIGHT).contains(&pos.y);
+                if sketch.is_drawing() || !on_canvas {
+                    continue;
                 }
 
-                *last_mouse_position = Some(event.position);
+                touches.finger = Some(event.id);
+                begin_stroke(
+                    &mut sketch,
+                    &mut stabilizer,
+                    brush.stroke(),
+                    brush.stabilizer,
+                );
+                let (x, y) = stabilizer.push(pos.x, pos.y);
+                add_point(&mut sketch, &mut image_events, Vec2::new(x, y), &time);
             }
+            TouchPhase::Moved if touches.finger == Some(event.id) => {
+                let (x, y) = stabilizer.push(pos.x, pos.y);
+                add_point(&mut sketch, &mut image_events, Vec2::new(x, y), &time);
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                touches.down.retain(|&id| id != event.id);
+                if touches.finger == Some(event.id) {
+                    touches.finger = None;
+                    end_stroke(&mut sketch, &mut stabilizer, &mut image_events, &time);
+                }
+            }
+            _ => {}
         }
     }
+}
 
-    for event in mouse_button_input_events.iter() {
-        // info!("mouse_button_input_events: {:?}", event);
+// Convert a window position (y pointing up) to sketch coordinates (y pointing
+// down) on the canvas
+fn to_sketch(position: Vec2, transform: &GlobalTransform, style: &Style) -> Vec2 {
+    let width = if let Val::Px(x) = style.size.width {
+        x
+    } else {
+        0.
+    };
+    let height = if let Val::Px(x) = style.size.height {
+        x
+    } else {
+        0.
+    };
+
+    let x = position.x - transform.translation.x + width / 2.;
+    let y = position.y - transform.translation.y + height / 2.;
+    Vec2::new(x, CANVAS_HEIGHT - y)
+}
 
-        if event.state == ElementState::Released {
-            *last_mouse_position = None;
+fn begin_stroke(sketch: &mut Sketch, stabilizer: &mut Stabilizer, stroke: Stroke, strength: f32) {
+    sketch.begin_stroke(stroke);
+    stabilizer.reset();
+    stabilizer.strength = strength;
+}
+
+fn end_stroke(
+    sketch: &mut Sketch,
+    stabilizer: &mut Stabilizer,
+    image_events: &mut EventWriter<ImageEvent>,
+    time: &Time,
+) {
+    // Catch up with the pointer that the smoothed stroke lags behind
+    if let Some((x, y)) = stabilizer.finish() {
+        if sketch.is_drawing() {
+            add_point(sketch, image_events, Vec2::new(x, y), time);
         }
     }
+    if sketch.end_stroke() {
+        image_events.send(ImageEvent::StrokeEnd);
+    }
+}
+
+// Extend the current stroke to `pos`, in sketch coordinates
+fn add_point(
+    sketch: &mut Sketch,
+    image_events: &mut EventWriter<ImageEvent>,
+    pos: Vec2,
+    time: &Time,
+) {
+    let last = sketch
+        .current_stroke()
+        .and_then(|stroke| stroke.points.last())
+        .map_or(pos, |point| Vec2::new(point.x, point.y));
+    image_events.send(ImageEvent::Segment(last, pos));
+
+    sketch.push_point(pos.x, pos.y, time.seconds_since_startup());
 }
 
 pub fn update_canvas(
     mut image_events: EventReader<ImageEvent>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     mut textures: ResMut<Assets<Texture>>,
+    sketch: Res<Sketch>,
+    mut rasterizer: Local<StrokeRasterizer>,
+    // Loaded image at the resolution of the texture
+    mut background: Local<Option<RgbImage>>,
     mut canvas: Query<(&bevy::ui::Node, &mut Handle<ColorMaterial>), With<Canvas>>,
 ) {
     for event in image_events.iter() {
@@ -257,55 +396,69 @@ pub fn update_canvas(
             .get_mut(material.texture.as_ref().unwrap())
             .unwrap();
 
+        let (width, height) = (texture.size.width, texture.size.height);
+        // From sketch coordinates to texture pixels
+        let scale = (width as f32 / CANVAS_WIDTH, height as f32 / CANVAS_HEIGHT);
+
         match event {
-            ImageEvent::DrawPos(pos) => {
-                let x_scale = texture.size.width as f32 / CANVAS_WIDTH;
-                let y_scale = texture.size.height as f32 / CANVAS_HEIGHT;
-                let line_scale = 2;
-                let line_radius = 1;
-
-                for i in -line_radius..=line_radius {
-                    for j in -line_radius..=line_radius {
-                        let target_point = Vec2::new(pos.x + i as f32, pos.y + j as f32);
-                        if pos.distance(target_point) < line_radius as f32 {
-                            for i in 0..=line_scale {
-                                for j in 0..=line_scale {
-                                    let x = target_point.x * x_scale;
-                                    let y = (CANVAS_HEIGHT - target_point.y) * y_scale;
-
-                                    set_pixel(x as i32 + i, y as i32 + j, Color::BLACK, texture);
-                                }
-                            }
-                        }
-                    }
+            ImageEvent::Segment(from, to) => {
+                // The stroke may already have ended if the button was released
+                // in the same frame
+                let stroke = match sketch.strokes.last() {
+                    Some(stroke) => stroke,
+                    None => continue,
+                };
+                if !rasterizer.is_active() {
+                    rasterizer.begin(&texture.data, width, height);
                 }
+
+                rasterizer.segment(
+                    &mut texture.data,
+                    (from.x * scale.0, from.y * scale.1),
+                    (to.x * scale.0, to.y * scale.1),
+                    stroke.radius * (scale.0 + scale.1) / 2.,
+                    stroke.color,
+                );
             }
             ImageEvent::Clear => {
-                for x in 0..texture.size.width as i32 {
-                    for y in 0..texture.size.height as i32 {
-                        set_pixel(x, y, Color::WHITE, texture);
-                    }
-                }
+                rasterizer.end();
+                *background = None;
+                fill(Color::WHITE, texture);
+            }
+            ImageEvent::Redraw => {
+                rasterizer.end();
+                paint_background(background.as_ref(), texture);
+                raster::render(&sketch.strokes, &mut texture.data, width, height, scale);
+            }
+            ImageEvent::StrokeEnd => rasterizer.end(),
+            ImageEvent::Background(img) => {
+                rasterizer.end();
+                *background = Some(raster::fit(img, width, height));
+                paint_background(background.as_ref(), texture);
+                raster::render(&sketch.strokes, &mut texture.data, width, height, scale);
             }
         }
     }
 }
 
-fn set_pixel(x: i32, y: i32, color: Color, texture: &mut Texture) {
-    if x < 0 || texture.size.width as i32 - 1 < x {
-        return;
-    }
-    if y < 0 || texture.size.height as i32 - 1 < y {
-        return;
-    }
+fn paint_background(background: Option<&RgbImage>, texture: &mut Texture) {
+    let background = match background {
+        Some(background) => background,
+        None => return fill(Color::WHITE, texture),
+    };
 
-    let x = x as usize;
-    let h = (y as u32 * texture.size.width) as usize;
-    let offset = (x + h) * 4;
+    for (pixel, value) in texture.data.chunks_exact_mut(4).zip(background.pixels()) {
+        pixel[..3].copy_from_slice(&value.0);
+    }
+}
 
-    texture.data[offset] = (color.r() * 255.) as u8;
-    texture.data[offset + 1] = (color.g() * 255.) as u8;
-    texture.data[offset + 2] = (color.b() * 255.) as u8;
+fn fill(color: Color, texture: &mut Texture) {
+    let rgb = [color.r(), color.g(), color.b()];
+    for pixel in texture.data.chunks_exact_mut(4) {
+        for (value, channel) in pixel.iter_mut().zip(rgb.iter()) {
+            *value = (channel * 255.) as u8;
+        }
+    }
 }
 
 #[allow(unused)]
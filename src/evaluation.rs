@@ -0,0 +1,230 @@
+// Accuracy of a classifier over labelled samples, as a confusion matrix with
+// a row for every true class and a column for every predicted one.
+
+use std::fmt::Write;
+
+use crate::labels::Labels;
+
+#[derive(Clone, Debug, Default)]
+pub struct ConfusionMatrix {
+    counts: Vec<Vec<u64>>,
+}
+
+impl ConfusionMatrix {
+    pub fn new(classes: usize) -> Self {
+        ConfusionMatrix {
+            counts: vec![vec![0; classes]; classes],
+        }
+    }
+
+    // Grows to fit classes the model has but the labels do not
+    pub fn add(&mut self, truth: usize, predicted: usize) {
+        let classes = self.classes().max(truth + 1).max(predicted + 1);
+        if classes > self.classes() {
+            for row in self.counts.iter_mut() {
+                row.resize(classes, 0);
+            }
+            self.counts.resize(classes, vec![0; classes]);
+        }
+
+        self.counts[truth][predicted] += 1;
+    }
+
+    pub fn classes(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn count(&self, truth: usize, predicted: usize) -> u64 {
+        self.counts[truth][predicted]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().flatten().sum()
+    }
+
+    // Number of samples of the class
+    pub fn support(&self, class: usize) -> u64 {
+        self.counts[class].iter().sum()
+    }
+
+    pub fn accuracy(&self) -> Option<f64> {
+        let correct: u64 = (0..self.classes()).map(|c| self.counts[c][c]).sum();
+        ratio(correct, self.total())
+    }
+
+    // None if the class was never predicted
+    pub fn precision(&self, class: usize) -> Option<f64> {
+        let predicted: u64 = self.counts.iter().map(|row| row[class]).sum();
+        ratio(self.counts[class][class], predicted)
+    }
+
+    // None if there are no samples of the class
+    pub fn recall(&self, class: usize) -> Option<f64> {
+        ratio(self.counts[class][class], self.support(class))
+    }
+
+    // Overall accuracy, per-class precision and recall and the matrix itself
+    pub fn report(&self, labels: &Labels) -> String {
+        let percent = |value: Option<f64>| match value {
+            Some(value) => format!("{:.1}%", value * 100.0),
+            None => "-".to_string(),
+        };
+        let names: Vec<String> = (0..self.classes()).map(|c| labels.name(c)).collect();
+        let width = names
+            .iter()
+            .map(|name| name.len())
+            .max()
+            .unwrap_or(0)
+            .max(5);
+
+        let mut report = String::new();
+        writeln!(
+            report,
+            "Accuracy: {} of {} samples",
+            percent(self.accuracy()),
+            self.total()
+        )
+        .unwrap();
+
+        writeln!(report).unwrap();
+        writeln!(
+            report,
+            "{:<width$}  {:>9}  {:>9}  {:>7}",
+            "class",
+            "precision",
+            "recall",
+            "samples",
+            width = width
+        )
+        .unwrap();
+        for (class, name) in names.iter().enumerate() {
+            writeln!(
+                report,
+                "{:<width$}  {:>9}  {:>9}  {:>7}",
+                name,
+                percent(self.precision(class)),
+                percent(self.recall(class)),
+                self.support(class),
+                width = width
+            )
+            .unwrap();
+        }
+
+        // Rows are true classes, columns predicted ones
+        let column = names
+            .iter()
+            .map(|name| name.len())
+            .max()
+            .unwrap_or(0)
+            .max(6);
+        writeln!(report).unwrap();
+        write!(
+            report,
+            "{:<width$}",
+            "true \\ predicted",
+            width = width.max(16)
+        )
+        .unwrap();
+        for name in names.iter() {
+            write!(report, "  {:>column$}", name, column = column).unwrap();
+        }
+        writeln!(report).unwrap();
+        for (truth, name) in names.iter().enumerate() {
+            write!(report, "{:<width$}", name, width = width.max(16)).unwrap();
+            for count in self.counts[truth].iter() {
+                write!(report, "  {:>column$}", count, column = column).unwrap();
+            }
+            writeln!(report).unwrap();
+        }
+
+        report
+    }
+
+    // The matrix with a header row of predicted classes and a first column of
+    // true classes
+    pub fn to_csv(&self, labels: &Labels) -> String {
+        let names: Vec<String> = (0..self.classes())
+            .map(|c| csv_field(&labels.name(c)))
+            .collect();
+
+        let mut csv = String::new();
+        writeln!(csv, "true\\predicted,{}", names.join(",")).unwrap();
+        for (truth, name) in names.iter().enumerate() {
+            let counts: Vec<String> = self.counts[truth].iter().map(|n| n.to_string()).collect();
+            writeln!(csv, "{},{}", name, counts.join(",")).unwrap();
+        }
+
+        csv
+    }
+}
+
+fn ratio(numerator: u64, denominator: u64) -> Option<f64> {
+    if denominator == 0 {
+        None
+    } else {
+        Some(numerator as f64 / denominator as f64)
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precision_and_recall() {
+        let mut matrix = ConfusionMatrix::new(2);
+        matrix.add(0, 0);
+        matrix.add(0, 0);
+        matrix.add(0, 1);
+        matrix.add(1, 1);
+
+        assert_eq!(matrix.total(), 4);
+        assert_eq!(matrix.support(0), 3);
+        assert_eq!(matrix.accuracy(), Some(0.75));
+        assert_eq!(matrix.precision(0), Some(1.0));
+        assert_eq!(matrix.precision(1), Some(0.5));
+        assert_eq!(matrix.recall(0), Some(2.0 / 3.0));
+        assert_eq!(matrix.recall(1), Some(1.0));
+    }
+
+    #[test]
+    fn empty_classes() {
+        let matrix = ConfusionMatrix::new(2);
+        assert_eq!(matrix.accuracy(), None);
+        assert_eq!(matrix.precision(0), None);
+        assert_eq!(matrix.recall(1), None);
+    }
+
+    #[test]
+    fn grows_for_unlabelled_classes() {
+        let mut matrix = ConfusionMatrix::new(2);
+        matrix.add(1, 3);
+
+        assert_eq!(matrix.classes(), 4);
+        assert_eq!(matrix.count(1, 3), 1);
+        assert_eq!(matrix.count(3, 1), 0);
+        assert_eq!(matrix.total(), 1);
+    }
+
+    #[test]
+    fn csv() {
+        let mut matrix = ConfusionMatrix::new(2);
+        matrix.add(0, 1);
+        let labels = Labels::parse("say \"hi\"\n");
+
+        assert_eq!(
+            matrix.to_csv(&labels),
+            "true\\predicted,\"say \"\"hi\"\"\",class 1\n\"say \"\"hi\"\"\",0,1\nclass 1,0,0\n"
+        );
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("a"), "a");
+    }
+}
@@ -0,0 +1,171 @@
+// Saving the sketch. Native builds write files to the working directory with
+// a timestamp in their name, the web build hands them to the browser as a
+// download.
+
+use bevy::prelude::*;
+use image::{codecs::png::PngEncoder, ColorType};
+use sketch_assist::{
+    quickdraw::Drawing,
+    sketch::{Sketch, Tool},
+    svg,
+};
+
+use crate::draw::{ctrl_pressed, Canvas, CANVAS_HEIGHT, CANVAS_WIDTH};
+use crate::model::texture_to_image;
+
+// Ctrl+S saves the canvas at the resolution of its texture as PNG
+pub fn save_sketch(
+    keyboard_input: Res<Input<KeyCode>>,
+    materials: Res<Assets<ColorMaterial>>,
+    textures: Res<Assets<Texture>>,
+    canvas: Query<&Handle<ColorMaterial>, With<Canvas>>,
+) {
+    if !ctrl_pressed(&keyboard_input) || !keyboard_input.just_pressed(KeyCode::S) {
+        return;
+    }
+
+    let texture = canvas
+        .iter()
+        .next()
+        .and_then(|mat| materials.get(mat))
+        .and_then(|material| material.texture.as_ref())
+        .and_then(|texture| textures.get(texture));
+    let texture = match texture {
+        Some(texture) => texture,
+        None => {
+            warn!("Canvas is not ready, nothing to save");
+            return;
+        }
+    };
+
+    let img = texture_to_image(texture);
+    let mut png = Vec::new();
+    let result = PngEncoder::new(&mut png)
+        .encode(img.as_raw(), img.width(), img.height(), ColorType::Rgb8)
+        .map_err(anyhow::Error::from)
+        .and_then(|()| save_file(&file_name("png"), &png, "image/png"));
+    if let Err(err) = result {
+        error!("Could not save the sketch: {:#}", err);
+    }
+}
+
+// Ctrl+E saves the strokes as a Quick Draw ndjson line
+pub fn export_quickdraw(keyboard_input: Res<Input<KeyCode>>, sketch: Res<Sketch>) {
+    if !ctrl_pressed(&keyboard_input) || !keyboard_input.just_pressed(KeyCode::E) {
+        return;
+    }
+
+    // Erased ink would come back in the exported drawing
+    let erased = sketch
+        .strokes
+        .iter()
+        .filter(|stroke| stroke.tool == Tool::Eraser)
+        .count();
+    if erased > 0 {
+        warn!(
+            "Quick Draw drawings have no eraser, {} eraser strokes are left out of the export",
+            erased
+        );
+    }
+
+    let drawing = Drawing {
+        timestamp: timestamp(),
+        ..Drawing::from_sketch(&sketch)
+    };
+    let ndjson = drawing.to_ndjson() + "\n";
+    if let Err(err) = save_file(
+        &file_name("ndjson"),
+        ndjson.as_bytes(),
+        "application/x-ndjson",
+    ) {
+        error!("Could not export the sketch: {:#}", err);
+    }
+}
+
+// Ctrl+G saves the strokes as SVG
+pub fn export_svg(keyboard_input: Res<Input<KeyCode>>, sketch: Res<Sketch>) {
+    if !ctrl_pressed(&keyboard_input) || !keyboard_input.just_pressed(KeyCode::G) {
+        return;
+    }
+
+    let svg = svg::to_svg(&sketch, CANVAS_WIDTH, CANVAS_HEIGHT);
+    if let Err(err) = save_file(&file_name("svg"), svg.as_bytes(), "image/svg+xml") {
+        error!("Could not export the sketch: {:#}", err);
+    }
+}
+
+// In the format of the Quick Draw dataset
+#[cfg(not(target_arch = "wasm32"))]
+fn timestamp() -> String {
+    chrono::Utc::now()
+        .format("%Y-%m-%d %H:%M:%S%.6f UTC")
+        .to_string()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn timestamp() -> String {
+    js_sys::Date::new_0().to_iso_string().into()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn file_name(extension: &str) -> String {
+    format!(
+        "sketch-{}.{}",
+        chrono::Local::now().format("%Y%m%d-%H%M%S"),
+        extension
+    )
+}
+
+// Browsers number downloads with the same name themselves
+#[cfg(target_arch = "wasm32")]
+pub fn file_name(extension: &str) -> String {
+    format!("sketch.{}", extension)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_file(file_name: &str, bytes: &[u8], _mime: &str) -> anyhow::Result<()> {
+    std::fs::write(file_name, bytes)?;
+    info!("Saved {}", file_name);
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn save_file(file_name: &str, bytes: &[u8], mime: &str) -> anyhow::Result<()> {
+    download(file_name, bytes, mime).map_err(|err| anyhow::anyhow!("{:?}", err))
+}
+
+// Time the browser gets to start a download before its URL is revoked
+#[cfg(target_arch = "wasm32")]
+const REVOKE_DELAY_MS: i32 = 10_000;
+
+// Click a temporary link to an object URL holding `bytes`
+#[cfg(target_arch = "wasm32")]
+fn download(file_name: &str, bytes: &[u8], mime: &str) -> Result<(), wasm_bindgen::JsValue> {
+    use wasm_bindgen::{closure::Closure, JsCast};
+
+    let parts = js_sys::Array::new();
+    parts.push(&js_sys::Uint8Array::from(bytes));
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(
+        &parts,
+        web_sys::BlobPropertyBag::new().type_(mime),
+    )?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)?;
+
+    let window = web_sys::window().ok_or("no window")?;
+    let document = window.document().ok_or("no document")?;
+    let link: web_sys::HtmlAnchorElement = document.create_element("a")?.dyn_into()?;
+    link.set_href(&url);
+    link.set_download(file_name);
+    link.click();
+
+    // Some browsers start the download asynchronously and cancel it if the URL
+    // is revoked right away
+    let revoke = Closure::once_into_js(move || {
+        let _ = web_sys::Url::revoke_object_url(&url);
+    });
+    window.set_timeout_with_callback_and_timeout_and_arguments_0(
+        revoke.unchecked_ref(),
+        REVOKE_DELAY_MS,
+    )?;
+    Ok(())
+}
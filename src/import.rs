@@ -0,0 +1,281 @@
+// Loading files onto the canvas. Files can be dropped onto the window (or
+// the page on the web) or given as the first command line argument, and the
+// I key loads the test image. Images are shown under the strokes, Quick Draw
+// ndjson files are turned into strokes one drawing at a time.
+
+use bevy::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::window::FileDragAndDrop;
+use image::RgbaImage;
+use sketch_assist::{quickdraw::Drawing, sketch::Sketch};
+use std::sync::{Arc, Mutex};
+
+use crate::brush::Brush;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::cli::AppArgs;
+use crate::draw::{
+    clear_inference, Canvas, GalleryImage, ImageEvent, TestCanvas, CANVAS_HEIGHT, CANVAS_WIDTH,
+};
+
+// Contents of files waiting to be imported, with their names. Shared with the
+// browser callbacks on the web, which fill it asynchronously.
+#[derive(Clone, Default)]
+pub struct DroppedFiles(Arc<Mutex<Vec<(String, Vec<u8>)>>>);
+
+impl DroppedFiles {
+    pub fn push(&self, name: String, bytes: Vec<u8>) {
+        self.0.lock().unwrap().push((name, bytes));
+    }
+
+    fn take(&self) -> Vec<(String, Vec<u8>)> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn open_command_line_file(args: Res<AppArgs>, dropped: Res<DroppedFiles>) {
+    if let Some(path) = args.file.as_ref() {
+        read_file(&dropped, path);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn open_dropped_files(
+    mut drag_and_drop_events: EventReader<FileDragAndDrop>,
+    dropped: Res<DroppedFiles>,
+) {
+    for event in drag_and_drop_events.iter() {
+        if let FileDragAndDrop::DroppedFile { path_buf, .. } = event {
+            read_file(&dropped, path_buf);
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_file(dropped: &DroppedFiles, path: &std::path::Path) {
+    match std::fs::read(path) {
+        Ok(bytes) => dropped.push(path.display().to_string(), bytes),
+        Err(err) => error!("Could not read {:?}: {}", path, err),
+    }
+}
+
+// winit does not report files dropped on the page, so listen to the drop
+// events of the document directly
+#[cfg(target_arch = "wasm32")]
+pub fn listen_for_dropped_files(dropped: Res<DroppedFiles>) {
+    use wasm_bindgen::{closure::Closure, JsCast};
+
+    let document = match web_sys::window().and_then(|window| window.document()) {
+        Some(document) => document,
+        None => return,
+    };
+
+    // Dropping is only allowed if dragover is cancelled
+    let dragover = Closure::wrap(Box::new(|event: web_sys::DragEvent| {
+        event.prevent_default();
+    }) as Box<dyn FnMut(_)>);
+
+    let dropped = dropped.clone();
+    let drop = Closure::wrap(Box::new(move |event: web_sys::DragEvent| {
+        event.prevent_default();
+
+        let files = match event.data_transfer().and_then(|data| data.files()) {
+            Some(files) => files,
+            None => return,
+        };
+        for i in 0..files.length() {
+            if let Some(file) = files.get(i) {
+                read_web_file(&dropped, file);
+            }
+        }
+    }) as Box<dyn FnMut(_)>);
+
+    let _ =
+        document.add_event_listener_with_callback("dragover", dragover.as_ref().unchecked_ref());
+    let _ = document.add_event_listener_with_callback("drop", drop.as_ref().unchecked_ref());
+
+    // The listeners live as long as the page
+    dragover.forget();
+    drop.forget();
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_web_file(dropped: &DroppedFiles, file: web_sys::File) {
+    use wasm_bindgen::{closure::Closure, JsCast};
+
+    let reader = match web_sys::FileReader::new() {
+        Ok(reader) => reader,
+        Err(_) => return,
+    };
+
+    let name = file.name();
+    let dropped = dropped.clone();
+    let loaded_reader = reader.clone();
+    let onload = Closure::once_into_js(move || {
+        if let Ok(buffer) = loaded_reader.result() {
+            dropped.push(name, js_sys::Uint8Array::new(&buffer).to_vec());
+        }
+    });
+    reader.set_onload(Some(onload.unchecked_ref()));
+
+    let _ = reader.read_as_array_buffer(&file);
+}
+
+// Drawings of the last imported ndjson file, N shows the next one
+#[derive(Default)]
+pub struct QuickDrawDrawings {
+    drawings: Vec<Drawing>,
+    current: usize,
+}
+
+// Put dropped files onto the canvas in place of the sketch
+pub fn import_files(
+    dropped: Res<DroppedFiles>,
+    materials: Res<Assets<ColorMaterial>>,
+    textures: Res<Assets<Texture>>,
+    brush: Res<Brush>,
+    mut quickdraw: ResMut<QuickDrawDrawings>,
+    mut image_events: EventWriter<ImageEvent>,
+    mut sketch: ResMut<Sketch>,
+    mut gallery: Query<&mut Visible, With<GalleryImage>>,
+    canvas: Query<&Handle<ColorMaterial>, With<Canvas>>,
+) {
+    // A file given on the command line arrives before the canvas is loaded
+    let canvas_ready = canvas
+        .iter()
+        .next()
+        .and_then(|mat| materials.get(mat))
+        .and_then(|material| material.texture.as_ref())
+        .and_then(|texture| textures.get(texture))
+        .is_some();
+    if !canvas_ready {
+        return;
+    }
+
+    for (name, bytes) in dropped.take() {
+        if name.ends_with(".ndjson") || name.ends_with(".json") {
+            let drawings = std::str::from_utf8(&bytes)
+                .map_err(anyhow::Error::from)
+                .and_then(Drawing::parse_ndjson);
+            match drawings {
+                Ok(drawings) if !drawings.is_empty() => {
+                    info!("Loaded {} drawings from {}", drawings.len(), name);
+                    *quickdraw = QuickDrawDrawings {
+                        drawings,
+                        current: 0,
+                    };
+                    show_drawing(
+                        &quickdraw.drawings[0],
+                        &brush,
+                        &mut image_events,
+                        &mut sketch,
+                        &mut gallery,
+                    );
+                }
+                Ok(_) => warn!("No drawings in {}", name),
+                Err(err) => error!("Could not load {}: {:#}", name, err),
+            }
+            continue;
+        }
+
+        let img = match image::load_from_memory(&bytes) {
+            Ok(img) => img.to_rgba8(),
+            Err(err) => {
+                error!("Could not load {}: {}", name, err);
+                continue;
+            }
+        };
+
+        info!("Loaded {}", name);
+        show_image(img, &mut image_events, &mut sketch, &mut gallery);
+    }
+}
+
+// I loads the test image
+pub fn load_test_image(
+    keyboard_input: Res<Input<KeyCode>>,
+    materials: Res<Assets<ColorMaterial>>,
+    textures: Res<Assets<Texture>>,
+    mut image_events: EventWriter<ImageEvent>,
+    mut sketch: ResMut<Sketch>,
+    mut gallery: Query<&mut Visible, With<GalleryImage>>,
+    test_canvas: Query<&Handle<ColorMaterial>, With<TestCanvas>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::I) {
+        return;
+    }
+
+    let texture = test_canvas
+        .iter()
+        .next()
+        .and_then(|mat| materials.get(mat))
+        .and_then(|material| material.texture.as_ref())
+        .and_then(|texture| textures.get(texture));
+    let img = texture.and_then(|texture| {
+        RgbaImage::from_raw(
+            texture.size.width,
+            texture.size.height,
+            texture.data.clone(),
+        )
+    });
+
+    match img {
+        Some(img) => show_image(img, &mut image_events, &mut sketch, &mut gallery),
+        None => warn!("Test image is not loaded yet"),
+    }
+}
+
+pub fn next_drawing(
+    keyboard_input: Res<Input<KeyCode>>,
+    brush: Res<Brush>,
+    mut quickdraw: ResMut<QuickDrawDrawings>,
+    mut image_events: EventWriter<ImageEvent>,
+    mut sketch: ResMut<Sketch>,
+    mut gallery: Query<&mut Visible, With<GalleryImage>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::N) || quickdraw.drawings.is_empty() {
+        return;
+    }
+
+    quickdraw.current = (quickdraw.current + 1) % quickdraw.drawings.len();
+    show_drawing(
+        &quickdraw.drawings[quickdraw.current],
+        &brush,
+        &mut image_events,
+        &mut sketch,
+        &mut gallery,
+    );
+}
+
+// Replace the sketch with the strokes of `drawing` in the style of the pen
+fn show_drawing(
+    drawing: &Drawing,
+    brush: &Brush,
+    image_events: &mut EventWriter<ImageEvent>,
+    sketch: &mut Sketch,
+    gallery: &mut Query<&mut Visible, With<GalleryImage>>,
+) {
+    clear_inference(gallery);
+
+    sketch.clear();
+    sketch.strokes = drawing.to_strokes(CANVAS_WIDTH, CANVAS_HEIGHT, &brush.pen_stroke());
+    image_events.send(ImageEvent::Clear);
+    image_events.send(ImageEvent::Redraw);
+
+    if !drawing.word.is_empty() {
+        info!("Showing a drawing of {}", drawing.word);
+    }
+}
+
+fn show_image(
+    img: RgbaImage,
+    image_events: &mut EventWriter<ImageEvent>,
+    sketch: &mut Sketch,
+    gallery: &mut Query<&mut Visible, With<GalleryImage>>,
+) {
+    clear_inference(gallery);
+
+    sketch.clear();
+    image_events.send(ImageEvent::Clear);
+    image_events.send(ImageEvent::Background(img));
+}
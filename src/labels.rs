@@ -0,0 +1,132 @@
+// Class labels of a model, read from a sidecar file next to the .onnx file
+// (`cnn_sketch_3class.onnx` -> `cnn_sketch_3class.labels`).
+//
+// One class per line in the order of the model output, optionally followed by
+// a comma and the file name prefix of its reference images in the assets
+// folder. Empty lines and lines starting with '#' are ignored.
+//
+//     rabbit,rabbit
+//     axe,axe
+//     smiley face,smile
+
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClassLabel {
+    pub name: String,
+    pub image_prefix: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Labels {
+    classes: Vec<ClassLabel>,
+}
+
+impl Labels {
+    pub fn parse(text: &str) -> Self {
+        let classes = text
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let mut fields = line.splitn(2, ',').map(|field| field.trim());
+                let name = fields.next().unwrap_or_default().to_string();
+                let image_prefix = fields
+                    .next()
+                    .filter(|prefix| !prefix.is_empty())
+                    .map(|prefix| prefix.to_string());
+
+                ClassLabel { name, image_prefix }
+            })
+            .collect();
+
+        Labels { classes }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn sidecar_path(model_path: &Path) -> PathBuf {
+        model_path.with_extension("labels")
+    }
+
+    pub fn len(&self) -> usize {
+        self.classes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty()
+    }
+
+    pub fn get(&self, class: usize) -> Option<&ClassLabel> {
+        self.classes.get(class)
+    }
+
+    // Name of the class, or "class N" if the labels do not cover it
+    pub fn name(&self, class: usize) -> String {
+        match self.get(class) {
+            Some(label) => label.name.clone(),
+            None => format!("class {}", class),
+        }
+    }
+
+    // Class whose name or image prefix is `name`, ignoring case and treating
+    // '_' like ' ' as in the file names of the Quick Draw dataset
+    pub fn find(&self, name: &str) -> Option<usize> {
+        let normalize = |name: &str| name.trim().to_lowercase().replace('_', " ");
+        let name = normalize(name);
+
+        self.classes.iter().position(|label| {
+            normalize(&label.name) == name
+                || label
+                    .image_prefix
+                    .as_deref()
+                    .map_or(false, |prefix| normalize(prefix) == name)
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ClassLabel> {
+        self.classes.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LABELS: &str = "# comment\n\nrabbit,rabbit\n axe \nsmiley face , smile\nfoo,\n";
+
+    #[test]
+    fn parse() {
+        let labels = Labels::parse(LABELS);
+        assert_eq!(labels.len(), 4);
+        assert_eq!(
+            labels.get(0).unwrap().image_prefix.as_deref(),
+            Some("rabbit")
+        );
+        assert_eq!(
+            labels.get(1),
+            Some(&ClassLabel {
+                name: "axe".to_string(),
+                image_prefix: None
+            })
+        );
+        assert_eq!(labels.get(2).unwrap().name, "smiley face");
+        assert_eq!(
+            labels.get(2).unwrap().image_prefix.as_deref(),
+            Some("smile")
+        );
+        assert_eq!(labels.get(3).unwrap().image_prefix, None);
+        assert_eq!(labels.name(7), "class 7");
+    }
+
+    #[test]
+    fn find() {
+        let labels = Labels::parse(LABELS);
+        assert_eq!(labels.find("Rabbit"), Some(0));
+        assert_eq!(labels.find("smiley_face"), Some(2));
+        assert_eq!(labels.find("smile"), Some(2));
+        assert_eq!(labels.find("cat"), None);
+    }
+}
@@ -0,0 +1,12 @@
+// Headless part of Sketch Assist. Nothing in here depends on Bevy so the
+// classifier can be used from tests, command line tools or a server.
+
+pub mod classifier;
+pub mod evaluation;
+pub mod labels;
+pub mod preprocess;
+pub mod quickdraw;
+pub mod raster;
+pub mod sketch;
+pub mod stabilizer;
+pub mod svg;
@@ -1,12 +1,50 @@
 use bevy::prelude::*;
 
+mod brush;
+#[cfg(not(target_arch = "wasm32"))]
+mod cli;
 mod draw;
+mod export;
+mod import;
 mod model;
+#[cfg(not(target_arch = "wasm32"))]
+mod server;
 
-use draw::{clear_canvas, create_canvas, mouse_draw, update_canvas};
-use model::{infer_sketch, infer_timer, OnnxModelAsset, OnnxModelLoader};
+use brush::{adjust_brush, create_toolbar, update_toolbar, Brush};
+use draw::{clear_canvas, create_canvas, mouse_draw, touch_draw, undo_redo, update_canvas};
+use export::{export_quickdraw, export_svg, save_sketch};
+use import::{import_files, load_test_image, next_drawing, DroppedFiles, QuickDrawDrawings};
+use model::{
+    create_prediction_text, create_status_text, infer_sketch, infer_timer, reload_model,
+    request_inference, show_inference_result, switch_model, update_model_status, InferenceRequest,
+    InferenceResult, OnnxModelAsset, OnnxModelLoader,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use model::{poll_inference, ModelFolder};
+use sketch_assist::sketch::Sketch;
 
 fn main() {
+    #[cfg(not(target_arch = "wasm32"))]
+    let app_args = {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        if let Some(code) = cli::run(&args) {
+            std::process::exit(code);
+        }
+
+        let app_args = match cli::AppArgs::parse(&args) {
+            Ok(app_args) => app_args,
+            Err(err) => {
+                eprintln!("error: {:#}", err);
+                std::process::exit(2);
+            }
+        };
+        if app_args.help {
+            println!("{}", cli::USAGE);
+            std::process::exit(0);
+        }
+        app_args
+    };
+
     let window_desc = WindowDescriptor {
         width: 1350.0,
         height: 700.0,
@@ -21,17 +59,77 @@ fn main() {
     #[cfg(target_arch = "wasm32")]
     app.add_plugin(bevy_webgl2::WebGL2Plugin);
 
+    #[cfg(target_arch = "wasm32")]
+    app.add_startup_system(import::listen_for_dropped_files.system());
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if let Some(dir) = app_args.models.clone() {
+            app.insert_resource(ModelFolder(dir));
+        }
+        app.insert_resource(app_args);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    app.add_system(poll_inference.system())
+        .add_startup_system(import::open_command_line_file.system())
+        .add_system(import::open_dropped_files.system().before("import_files"));
+
     app.insert_resource(ClearColor(Color::SILVER))
         .add_asset::<OnnxModelAsset>()
         .init_asset_loader::<OnnxModelLoader>()
         .init_resource::<model::State>()
+        .init_resource::<Sketch>()
+        .init_resource::<Brush>()
+        .init_resource::<DroppedFiles>()
+        .init_resource::<QuickDrawDrawings>()
         .add_event::<draw::ImageEvent>()
+        .add_event::<InferenceRequest>()
+        .add_event::<InferenceResult>()
         .add_startup_system(setup.system())
-        .add_system(mouse_draw.system())
-        .add_system(update_canvas.system())
-        .add_system(clear_canvas.system())
-        .add_system(infer_sketch.system())
+        .add_startup_system(create_prediction_text.system())
+        .add_startup_system(create_status_text.system())
+        .add_startup_system(create_toolbar.system())
+        .add_system(adjust_brush.system().label("adjust_brush"))
+        .add_system(update_toolbar.system().after("adjust_brush"))
+        .add_system(mouse_draw.system().label("mouse_draw"))
+        .add_system(touch_draw.system().label("touch_draw"))
+        // Paint every image event of this frame before it is classified
+        .add_system(
+            update_canvas
+                .system()
+                .label("update_canvas")
+                .after("mouse_draw")
+                .after("touch_draw")
+                .after("clear_canvas")
+                .after("undo_redo")
+                .after("import_files")
+                .after("load_test_image")
+                .after("next_drawing"),
+        )
+        .add_system(clear_canvas.system().label("clear_canvas"))
+        .add_system(undo_redo.system().label("undo_redo"))
+        .add_system(save_sketch.system().after("update_canvas"))
+        .add_system(export_quickdraw.system())
+        .add_system(export_svg.system())
+        .add_system(import_files.system().label("import_files"))
+        .add_system(load_test_image.system().label("load_test_image"))
+        .add_system(next_drawing.system().label("next_drawing"))
+        .add_system(request_inference.system().label("request_inference"))
+        .add_system(reload_model.system().label("reload_model"))
+        .add_system(switch_model.system().label("switch_model"))
+        // Infer on the canvas after this frame's strokes have been drawn
+        .add_system(
+            infer_sketch
+                .system()
+                .after("update_canvas")
+                .after("request_inference")
+                .after("reload_model")
+                .after("switch_model"),
+        )
+        .add_system(show_inference_result.system())
         .add_system(infer_timer.system())
+        .add_system(update_model_status.system())
         .add_system(bevy::input::system::exit_on_esc_system.system())
         .run();
 }
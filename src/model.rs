@@ -1,23 +1,31 @@
+use anyhow::Context;
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::asset::{AssetServerSettings, FileAssetIo};
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::tasks::{AsyncComputeTaskPool, Task};
 use bevy::{
-    asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+    asset::{AssetLoader, BoxedFuture, LoadContext, LoadState, LoadedAsset},
     prelude::*,
     reflect::TypeUuid,
 };
-use image::{imageops::FilterType, ImageBuffer, RgbImage};
+#[cfg(not(target_arch = "wasm32"))]
+use futures_lite::future;
+use image::{ImageBuffer, RgbImage};
+use sketch_assist::{
+    classifier::{self, Prediction, SketchClassifier},
+    labels::Labels,
+    preprocess::PreprocessConfig,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
 use std::{
-    path::PathBuf,
-    time::{Duration, Instant},
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
 };
-use tract_ndarray::Array;
-use tract_onnx::prelude::*;
 use wasm_bindgen::prelude::*;
 
-use crate::draw::{
-    Canvas, ImageEvent, TestCanvas, CANVAS_HEIGHT, CANVAS_WIDTH, OFFSET, WINDOW_HEIGHT,
-    WINDOW_WIDTH,
-};
-
-const INPUT_IMG_SIZE: u32 = 128;
+use crate::draw::{Canvas, GalleryImage, ImageEvent, CANVAS_HEIGHT, CANVAS_WIDTH, OFFSET};
 
 #[wasm_bindgen]
 extern "C" {
@@ -38,31 +46,65 @@ macro_rules! console_log {
 #[derive(TypeUuid)]
 #[uuid = "ea2073f7-2a59-4983-85cd-6370ea9101a2"]
 pub struct OnnxModelAsset {
-    pub model: SimplePlan<
-        TypedFact,
-        Box<dyn TypedOp>,
-        tract_onnx::prelude::Graph<TypedFact, Box<dyn TypedOp>>,
-    >,
+    pub classifier: Arc<SketchClassifier>,
 }
 
-#[derive(Default)]
-pub struct OnnxModelLoader;
+// Messages of models that failed to load, by asset path. Bevy only keeps the
+// load state so the loader stores the reason here for the status banner.
+#[derive(Clone, Default)]
+pub struct ModelLoadErrors(Arc<Mutex<HashMap<PathBuf, String>>>);
+
+impl ModelLoadErrors {
+    fn set(&self, path: &Path, error: Option<&anyhow::Error>) {
+        let mut errors = self.0.lock().unwrap();
+        match error {
+            Some(error) => errors.insert(path.to_path_buf(), format!("{:#}", error)),
+            None => errors.remove(path),
+        };
+    }
+
+    pub fn get(&self, path: &Path) -> Option<String> {
+        self.0.lock().unwrap().get(path).cloned()
+    }
+}
+
+pub struct OnnxModelLoader {
+    errors: ModelLoadErrors,
+}
+
+impl FromWorld for OnnxModelLoader {
+    fn from_world(world: &mut World) -> Self {
+        let errors = world
+            .get_resource_or_insert_with(ModelLoadErrors::default)
+            .clone();
+        OnnxModelLoader { errors }
+    }
+}
 
 impl AssetLoader for OnnxModelLoader {
     fn load<'a>(
         &'a self,
-        mut bytes: &'a [u8],
+        bytes: &'a [u8],
         load_context: &'a mut LoadContext,
     ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
         Box::pin(async move {
-            let model = tract_onnx::onnx()
-                .model_for_read(&mut bytes)
-                .unwrap()
-                .into_optimized()?
-                .into_runnable()?;
-
-            load_context.set_default_asset(LoadedAsset::new(OnnxModelAsset { model }));
-            Ok(())
+            let path = load_context.path().to_path_buf();
+            match load_classifier(bytes, load_context)
+                .await
+                .with_context(|| format!("could not load {}", path.display()))
+            {
+                Ok(classifier) => {
+                    self.errors.set(&path, None);
+                    load_context.set_default_asset(LoadedAsset::new(OnnxModelAsset {
+                        classifier: Arc::new(classifier),
+                    }));
+                    Ok(())
+                }
+                Err(err) => {
+                    self.errors.set(&path, Some(&err));
+                    Err(err)
+                }
+            }
         })
     }
 
@@ -71,241 +113,623 @@ impl AssetLoader for OnnxModelLoader {
     }
 }
 
+// Read the model along with its sidecar labels and preprocessing files
+async fn load_classifier(
+    mut bytes: &[u8],
+    load_context: &mut LoadContext<'_>,
+) -> anyhow::Result<SketchClassifier> {
+    let path = load_context.path();
+    let mut classifier = SketchClassifier::from_reader(&mut bytes)?;
+
+    let labels_path = Labels::sidecar_path(path);
+    match load_context.read_asset_bytes(&labels_path).await {
+        Ok(labels) => {
+            classifier = classifier.with_labels(Labels::parse(&String::from_utf8_lossy(&labels)));
+        }
+        Err(err) => warn!("No labels for model ({:?}): {}", labels_path, err),
+    }
+
+    // Without a preprocessing file the defaults are used
+    let preprocess_path = PreprocessConfig::sidecar_path(path);
+    if let Ok(preprocess) = load_context.read_asset_bytes(&preprocess_path).await {
+        let preprocess = PreprocessConfig::parse(&String::from_utf8_lossy(&preprocess))
+            .with_context(|| format!("invalid {}", preprocess_path.display()))?;
+        classifier = classifier.with_preprocess(preprocess);
+    }
+
+    Ok(classifier)
+}
+
 #[derive(PartialEq)]
 pub enum InferenceState {
     Wait,
     Infer,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum InferenceMode {
+    // Infer when B is pressed
+    Manual,
+    // Infer after every stroke, undo and redo
+    Live,
+}
+
+// Folder of .onnx models to switch between, given with --models
+#[cfg(not(target_arch = "wasm32"))]
+pub struct ModelFolder(pub PathBuf);
+
 pub struct State {
+    // The model in use
     pub model: Handle<OnnxModelAsset>,
+    pub model_path: PathBuf,
+    // Every model that can be switched to with M, including the one in use
+    pub models: Vec<(PathBuf, Handle<OnnxModelAsset>)>,
+    pub current: usize,
     pub inference_state: InferenceState,
+    pub mode: InferenceMode,
+    // Bumped for every canvas snapshot sent to inference and on clear, so that
+    // results of outdated snapshots can be dropped
+    pub generation: u64,
+    // Whether the canvas has been classified since it was last cleared
+    pub inferred: bool,
+    // Number of times the model file changed and was loaded again
+    pub reloads: u32,
+    // Whether the model files can be watched for changes. Bevy panics on
+    // changes to files outside the assets folder, so models loaded from
+    // elsewhere turn watching off.
+    pub watchable: bool,
 }
 
-enum ImageClass {
-    Rabbit = 1,
-    Axe,
-    SmileyFace,
+// Ask `infer_sketch` to classify the current canvas
+pub struct InferenceRequest;
+
+// Inference of a canvas snapshot running on the compute task pool
+#[cfg(not(target_arch = "wasm32"))]
+pub struct InferenceTask {
+    generation: u64,
+    model: Arc<SketchClassifier>,
+    task: Task<anyhow::Result<Vec<Prediction>>>,
 }
 
+// The web build has no task pool, this only exists for the query in
+// `infer_sketch`
+#[cfg(target_arch = "wasm32")]
+pub struct InferenceTask;
+
+pub struct InferenceResult {
+    // Model that produced the predictions, for its labels
+    pub model: Arc<SketchClassifier>,
+    pub predictions: anyhow::Result<Vec<Prediction>>,
+}
+
+// Number of predictions listed below the result panel
+const TOP_K: usize = 5;
+
+pub const MODEL_PATH: &str = "cnn_sketch_3class.onnx";
+
+// Text listing the most probable classes of the last inference
+pub struct PredictionText;
+
+// Banner above the canvas showing whether the model can be used
+pub struct StatusText;
+
 impl FromWorld for State {
     fn from_world(world: &mut World) -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let paths = match world.get_resource::<ModelFolder>() {
+            Some(ModelFolder(dir)) => list_models(dir, &asset_root(world)),
+            None => Vec::new(),
+        };
+        #[cfg(target_arch = "wasm32")]
+        let paths: Vec<PathBuf> = Vec::new();
+        let paths = if paths.is_empty() {
+            vec![PathBuf::from(MODEL_PATH)]
+        } else {
+            paths
+        };
+
+        let watchable = paths.iter().all(|path| path.is_relative());
+
         let asset_server = world.get_resource::<AssetServer>().unwrap();
+        // Before loading, as files are only watched if they finish loading
+        // while watching is on
+        #[cfg(not(target_arch = "wasm32"))]
+        watch_for_model_changes(asset_server, watchable);
+
+        let models: Vec<_> = paths
+            .into_iter()
+            .map(|path| {
+                let handle = asset_server.load(path.as_path());
+                (path, handle)
+            })
+            .collect();
+
         State {
             inference_state: InferenceState::Wait,
-            model: asset_server.load("cnn_sketch_3class.onnx"),
+            mode: InferenceMode::Manual,
+            generation: 0,
+            inferred: false,
+            reloads: 0,
+            watchable,
+            model: models[0].1.clone(),
+            model_path: models[0].0.clone(),
+            models,
+            current: 0,
+        }
+    }
+}
+
+// The .onnx files in `dir`, by name. Their labels and preprocessing files are
+// loaded along with them. Files in the assets folder are given relative to it
+// like any other asset, files elsewhere by their absolute path.
+#[cfg(not(target_arch = "wasm32"))]
+fn list_models(dir: &Path, root: &Path) -> Vec<PathBuf> {
+    let entries = std::fs::canonicalize(dir).and_then(std::fs::read_dir);
+    let mut paths: Vec<PathBuf> = match entries {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == "onnx")
+            })
+            .collect(),
+        Err(err) => {
+            error!("Could not list the models in {}: {}", dir.display(), err);
+            return Vec::new();
+        }
+    };
+    paths.sort();
+    let paths: Vec<PathBuf> = paths
+        .into_iter()
+        .map(|path| match path.strip_prefix(root) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => path,
+        })
+        .collect();
+
+    if paths.is_empty() {
+        warn!("No .onnx models in {}", dir.display());
+    }
+    for path in paths.iter() {
+        info!("Found model {}", path.display());
+    }
+    paths
+}
+
+// Absolute path of the folder the asset server loads from
+#[cfg(not(target_arch = "wasm32"))]
+fn asset_root(world: &World) -> PathBuf {
+    let folder = world
+        .get_resource::<AssetServerSettings>()
+        .map_or("assets", |settings| settings.asset_folder.as_str());
+    let root = FileAssetIo::get_root_path().join(folder);
+    std::fs::canonicalize(&root).unwrap_or(root)
+}
+
+// L switches between manual and live mode
+pub fn request_inference(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut image_events: EventReader<ImageEvent>,
+    mut requests: EventWriter<InferenceRequest>,
+    mut state: ResMut<State>,
+) {
+    if keyboard_input.just_pressed(KeyCode::L) {
+        state.mode = match state.mode {
+            InferenceMode::Manual => InferenceMode::Live,
+            InferenceMode::Live => InferenceMode::Manual,
+        };
+    }
+
+    match state.mode {
+        InferenceMode::Manual => {
+            if keyboard_input.just_pressed(KeyCode::B)
+                && state.inference_state == InferenceState::Infer
+            {
+                requests.send(InferenceRequest);
+                state.inference_state = InferenceState::Wait;
+            }
+        }
+        InferenceMode::Live => {
+            let changed = image_events.iter().any(|event| {
+                matches!(
+                    event,
+                    ImageEvent::StrokeEnd | ImageEvent::Redraw | ImageEvent::Background(_)
+                )
+            });
+            if changed {
+                requests.send(InferenceRequest);
+            }
         }
     }
 }
 
 pub fn infer_sketch(
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
     mut image_events: EventReader<ImageEvent>,
-    keyboard_input: Res<Input<KeyCode>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut requests: EventReader<InferenceRequest>,
+    #[cfg(not(target_arch = "wasm32"))] thread_pool: Res<AsyncComputeTaskPool>,
+    #[cfg(target_arch = "wasm32")] mut results: EventWriter<InferenceResult>,
+    materials: Res<Assets<ColorMaterial>>,
     textures: Res<Assets<Texture>>,
     models: Res<Assets<OnnxModelAsset>>,
     mut state: ResMut<State>,
     drawable: Query<&Handle<ColorMaterial>, With<Canvas>>,
+    tasks: Query<Entity, With<InferenceTask>>,
+    mut prediction_text: Query<&mut Text, With<PredictionText>>,
 ) {
     // If canvas is cleared and nothing drawed then return without inference
     for event in image_events.iter() {
         match event {
-            ImageEvent::Clear => return,
+            ImageEvent::Clear => {
+                // Results for the sketch before clearing are not wanted anymore
+                state.generation += 1;
+                state.inferred = false;
+                for entity in tasks.iter() {
+                    commands.entity(entity).despawn();
+                }
+
+                for mut text in prediction_text.iter_mut() {
+                    text.sections[0].value.clear();
+                }
+                return;
+            }
             _ => (),
         }
     }
 
-    if keyboard_input.just_pressed(KeyCode::B) && state.inference_state == InferenceState::Infer {
-        for mat in drawable.iter() {
-            let material = &materials.get(mat).unwrap();
-            let texture = textures.get(material.texture.as_ref().unwrap()).unwrap();
-
-            let mut img: RgbImage = ImageBuffer::new(texture.size.width, texture.size.height);
-
-            // Copy image data from texture to ImageBuffer
-            for i in 0..texture.size.width {
-                for j in 0..texture.size.height {
-                    let idx = (i as usize + (j as u32 * texture.size.width) as usize) * 4;
-                    let r = texture.data[idx];
-                    let g = texture.data[idx + 1];
-                    let b = texture.data[idx + 2];
-                    img[(i as u32, j as u32)] = image::Rgb([r, g, b]);
+    // Several requests in the same frame are served by a single inference
+    if requests.iter().count() == 0 {
+        return;
+    }
+
+    let model = match models.get(state.model.as_weak::<OnnxModelAsset>()) {
+        Some(model) => model.classifier.clone(),
+        None => {
+            warn!("Model is not ready, skipping inference");
+            return;
+        }
+    };
+
+    for mat in drawable.iter() {
+        let material = &materials.get(mat).unwrap();
+        let texture = textures.get(material.texture.as_ref().unwrap()).unwrap();
+
+        let img = texture_to_image(texture);
+
+        // #[cfg(not(target_arch = "wasm32"))]
+        // img.save("image.png").unwrap();
+
+        // Only the newest snapshot is worth classifying. Dropping a task
+        // cancels it unless it is already running, and `poll_inference`
+        // ignores results of older generations.
+        for entity in tasks.iter() {
+            commands.entity(entity).despawn();
+        }
+        state.generation += 1;
+        state.inferred = true;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let task_model = model.clone();
+            let task = thread_pool.spawn(async move { classify(&task_model, &img) });
+            commands.spawn().insert(InferenceTask {
+                generation: state.generation,
+                model: model.clone(),
+                task,
+            });
+        }
+
+        // There are no threads on the web
+        #[cfg(target_arch = "wasm32")]
+        results.send(InferenceResult {
+            predictions: classify(&model, &img),
+            model: model.clone(),
+        });
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn poll_inference(
+    mut commands: Commands,
+    state: Res<State>,
+    mut tasks: Query<(Entity, &mut InferenceTask)>,
+    mut results: EventWriter<InferenceResult>,
+) {
+    for (entity, mut task) in tasks.iter_mut() {
+        if let Some(predictions) = future::block_on(future::poll_once(&mut task.task)) {
+            commands.entity(entity).despawn();
+
+            if task.generation == state.generation {
+                results.send(InferenceResult {
+                    model: task.model.clone(),
+                    predictions,
+                });
+            }
+        }
+    }
+}
+
+// Watch the asset folder so that a model overwritten by training is loaded
+// again
+#[cfg(not(target_arch = "wasm32"))]
+fn watch_for_model_changes(asset_server: &AssetServer, watchable: bool) {
+    if !watchable {
+        warn!("Not watching the models for changes, only models in the assets folder can be");
+        return;
+    }
+
+    if let Err(err) = asset_server.watch_for_changes() {
+        warn!("Could not watch the assets for changes: {:?}", err);
+    }
+}
+
+// Classify the canvas again with a model that was reloaded. Only the .onnx
+// file is watched, so touch it after editing its labels or preprocessing.
+pub fn reload_model(
+    mut asset_events: EventReader<AssetEvent<OnnxModelAsset>>,
+    mut requests: EventWriter<InferenceRequest>,
+    mut state: ResMut<State>,
+) {
+    for event in asset_events.iter() {
+        if let AssetEvent::Modified { handle } = event {
+            if *handle != state.model {
+                continue;
+            }
+
+            state.reloads += 1;
+            info!("Reloaded {}", state.model_path.display());
+            if state.inferred {
+                requests.send(InferenceRequest);
+            }
+        }
+    }
+}
+
+// M switches to the next model and classifies the canvas with it
+pub fn switch_model(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut requests: EventWriter<InferenceRequest>,
+    mut state: ResMut<State>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::M) || state.models.len() < 2 {
+        return;
+    }
+
+    state.current = (state.current + 1) % state.models.len();
+    let (path, handle) = state.models[state.current].clone();
+    info!("Switched to {}", path.display());
+    state.model = handle;
+    state.model_path = path;
+    state.reloads = 0;
+
+    if state.inferred {
+        requests.send(InferenceRequest);
+    }
+}
+
+pub fn show_inference_result(
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut results: EventReader<InferenceResult>,
+    mut prediction_text: Query<&mut Text, With<PredictionText>>,
+    mut gallery: Query<(&GalleryImage, &Handle<ColorMaterial>, &mut Visible)>,
+) {
+    for result in results.iter() {
+        let predictions = match &result.predictions {
+            Ok(predictions) => predictions,
+            Err(err) => {
+                error!("Inference failed: {:#}", err);
+                for mut text in prediction_text.iter_mut() {
+                    text.sections[0].value = format!("Inference failed: {}", err);
                 }
+                continue;
             }
+        };
 
-            // #[cfg(not(target_arch = "wasm32"))]
-            // img.save("image.png").unwrap();
-
-            let resized =
-                image::imageops::resize(&img, INPUT_IMG_SIZE, INPUT_IMG_SIZE, FilterType::Triangle);
-
-            // let tensor_image = tract_ndarray::Array4::from_shape_fn(
-            //     (1, 1, INPUT_IMG_SIZE as usize, INPUT_IMG_SIZE as usize),
-            //     |(_, _, y, x)| resized[(x as _, y as _)][0] as f32,
-            // );
-            // println!("tensor_image shape: {:?}", tensor_image.shape());
-            // for i in 0..10 {
-            //     for j in 0..10 {
-            //         let a = resized.get_pixel(i, j);
-            //         print!("({} {} {}),", a[0], a[1], a[2]);
-            //     }
-            //     println!();
-            // }
-
-            let tensor_image: Tensor = ((tract_ndarray::Array3::from_shape_fn(
-                (1, INPUT_IMG_SIZE as usize, INPUT_IMG_SIZE as usize),
-                |(_, y, x)| {
-                    // Convert RGB to gray scale value
-                    // let r = resized[(x as _, y as _)][0] as f32;
-                    // let g = resized[(x as _, y as _)][1] as f32;
-                    // let b = resized[(x as _, y as _)][2] as f32;
-                    // (r * 0.3 + g * 0.59 + b * 0.11) / 255.0
-                    resized[(x as _, y as _)][0] as f32 / 255.0
-                },
-            ) - 0.5)
-                / 0.5)
-                .into();
-
-            #[cfg(not(target_arch = "wasm32"))]
-            resized.save("resized.png").unwrap();
-
-            // dbg!(&tensor_image.shape());
-            // dbg!(&tensor_image);
-            for i in 0..10 {
-                for j in 0..10 {
-                    // print!("{:?} ", resized[(i, j)]);
-                    // dbg!(resized[(i, j)]);
-
-                    // let a = resized.get_pixel(i, j);
-                    // print!("[({} {} {}),", a[0], a[1], a[2]);
+        let labels = result.model.labels();
+        let summary = summarize(predictions, labels);
+
+        info!("{}", summary);
+
+        #[cfg(target_arch = "wasm32")]
+        console_log!("{}", summary);
+
+        for mut text in prediction_text.iter_mut() {
+            text.sections[0].value = summary.clone();
+        }
+
+        let image_prefix = predictions
+            .first()
+            .and_then(|best| labels.get(best.class))
+            .and_then(|label| label.image_prefix.as_deref());
+        match image_prefix {
+            Some(prefix) => show_infer_result(&asset_server, &mut materials, &mut gallery, prefix),
+            None => {
+                for (_, _, mut visible) in gallery.iter_mut() {
+                    visible.is_visible = false;
                 }
-                // println!();
             }
+        }
+    }
+}
 
-            #[cfg(not(target_arch = "wasm32"))]
-            let start = Instant::now();
+// Preprocess and run the model. This is moved onto the compute task pool on
+// native builds so it must not touch the ECS.
+fn classify(model: &SketchClassifier, img: &RgbImage) -> anyhow::Result<Vec<Prediction>> {
+    let preprocess = model.preprocess();
+    let resized = preprocess.resize(img);
 
-            // #[cfg(target_arch = "wasm32")]
-            // console_log!("aaaaaaa");
-            // return;
+    #[cfg(not(target_arch = "wasm32"))]
+    let start = Instant::now();
 
-            #[cfg(target_arch = "wasm32")]
-            time("infer");
+    #[cfg(target_arch = "wasm32")]
+    time("infer");
 
-            if let Some(model) = models.get(state.model.as_weak::<OnnxModelAsset>()) {
-                let result = model.model.run(tvec!(tensor_image)).unwrap();
+    let scores = model.run(preprocess.tensor(&resized))?;
 
-                // find and display the max value with its index
-                let best = result[0]
-                    .to_array_view::<f32>()
-                    .unwrap()
-                    .iter()
-                    .cloned()
-                    .zip(1..)
-                    .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    #[cfg(not(target_arch = "wasm32"))]
+    debug!("Inference time: {:?}", start.elapsed());
 
-                let (score, class) = best.unwrap();
+    #[cfg(target_arch = "wasm32")]
+    timeEnd("infer");
 
-                println!("{} {}", score, class);
+    Ok(classifier::rank(&scores))
+}
 
-                // let a = result[0].to_array_view::<f32>().unwrap();
-                // println!("{}", a);
+pub fn create_prediction_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position: Rect {
+                    left: Val::Px(OFFSET * 2. + CANVAS_WIDTH),
+                    top: Val::Px(OFFSET + CANVAS_HEIGHT + OFFSET / 4.),
+                    ..Default::default()
+                },
+                position_type: PositionType::Absolute,
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSans.ttf"),
+                    font_size: 24.,
+                    color: Color::BLACK,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(PredictionText);
+}
 
-                #[cfg(not(target_arch = "wasm32"))]
-                let duration = start.elapsed();
-                #[cfg(not(target_arch = "wasm32"))]
-                println!("Inference time: {:?}", duration);
+pub fn create_status_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position: Rect {
+                    left: Val::Px(OFFSET),
+                    top: Val::Px(OFFSET / 4.),
+                    ..Default::default()
+                },
+                position_type: PositionType::Absolute,
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSans.ttf"),
+                    font_size: 24.,
+                    color: Color::BLACK,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(StatusText);
+}
 
-                #[cfg(target_arch = "wasm32")]
-                console_log!("{} {}", score, class);
-                #[cfg(target_arch = "wasm32")]
-                timeEnd("infer");
+pub fn update_model_status(
+    asset_server: Res<AssetServer>,
+    models: Res<Assets<OnnxModelAsset>>,
+    state: Res<State>,
+    errors: Res<ModelLoadErrors>,
+    mut status_text: Query<&mut Text, With<StatusText>>,
+) {
+    let mode = match state.mode {
+        InferenceMode::Manual => "manual inference (B), L for live",
+        InferenceMode::Live => "live inference, L for manual",
+    };
+    // e.g. "cnn_sketch_3class.onnx v2, 3 classes at 128px (reloaded 3x)"
+    let mut name = state
+        .model_path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    if let Some(model) = models.get(&state.model) {
+        let classifier = &model.classifier;
+        if classifier.version() != 0 {
+            name += &format!(" v{}", classifier.version());
+        }
+        if !classifier.labels().is_empty() {
+            name += &format!(", {} classes", classifier.labels().len());
+        }
+        name += &format!(" at {}px", classifier.preprocess().input_size);
+    }
+    if state.reloads > 0 {
+        name += &format!(" (reloaded {}x)", state.reloads);
+    }
+    if state.models.len() > 1 {
+        name += &format!(
+            " [{}/{}, M to switch]",
+            state.current + 1,
+            state.models.len()
+        );
+    }
+    if !state.watchable {
+        name += " (outside assets, not watched)";
+    }
 
-                show_infer_result(&mut commands, &asset_server, &mut materials, class);
-            }
+    let (status, color) = match asset_server.get_load_state(&state.model) {
+        LoadState::Loaded => (format!("Model {} ready", name), Color::DARK_GREEN),
+        LoadState::Failed => {
+            let reason = errors
+                .get(&state.model_path)
+                .unwrap_or_else(|| format!("could not load {}", state.model_path.display()));
+            (format!("Model failed: {}", reason), Color::RED)
+        }
+        _ => ("Model loading...".to_string(), Color::DARK_GRAY),
+    };
+
+    let status = format!("{} - {}", status, mode);
+
+    for mut text in status_text.iter_mut() {
+        // Avoid relayouting the text every frame
+        if text.sections[0].value != status {
+            text.sections[0].value = status.clone();
+            text.sections[0].style.color = color;
+        }
+    }
+}
 
-            state.inference_state = InferenceState::Wait;
+// e.g. "rabbit 81.2%  smiley face 15.0%  axe 3.8%"
+fn summarize(predictions: &[Prediction], labels: &Labels) -> String {
+    predictions
+        .iter()
+        .take(TOP_K)
+        .map(|p| format!("{} {:.1}%", labels.name(p.class), p.probability * 100.))
+        .collect::<Vec<_>>()
+        .join("  ")
+}
+
+pub fn texture_to_image(texture: &Texture) -> RgbImage {
+    let mut img: RgbImage = ImageBuffer::new(texture.size.width, texture.size.height);
+
+    // Copy image data from texture to ImageBuffer
+    for i in 0..texture.size.width {
+        for j in 0..texture.size.height {
+            let idx = (i as usize + (j as u32 * texture.size.width) as usize) * 4;
+            let r = texture.data[idx];
+            let g = texture.data[idx + 1];
+            let b = texture.data[idx + 2];
+            img[(i as u32, j as u32)] = image::Rgb([r, g, b]);
         }
     }
+
+    img
 }
 
 fn show_infer_result(
-    commands: &mut Commands,
     asset_server: &Res<AssetServer>,
     materials: &mut ResMut<Assets<ColorMaterial>>,
-    class: u32,
+    gallery: &mut Query<(&GalleryImage, &Handle<ColorMaterial>, &mut Visible)>,
+    image_prefix: &str,
 ) {
-    let path = match class {
-        1 => "rabbit",
-        2 => "axe",
-        3 => "smile",
-        _ => "err",
-    };
-    let path = path.to_string();
-
-    let texture1 = asset_server.load(PathBuf::from(path.clone() + "1.png"));
-    let texture2 = asset_server.load(PathBuf::from(path.clone() + "2.png"));
-    let texture3 = asset_server.load(PathBuf::from(path.clone() + "3.png"));
-    let texture4 = asset_server.load(PathBuf::from(path + "4.png"));
-
-    // Upper left
-    commands.spawn_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(CANVAS_WIDTH / 2., CANVAS_HEIGHT / 2.)),
-        material: materials.add(texture1.into()),
-        transform: Transform {
-            translation: Vec3::new(
-                WINDOW_WIDTH / 2. - OFFSET - CANVAS_WIDTH / 2. - CANVAS_WIDTH / 4.,
-                WINDOW_HEIGHT / 2. - OFFSET - CANVAS_HEIGHT / 4.,
-                0.,
-            ),
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-    // Upper right
-    commands.spawn_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(CANVAS_WIDTH / 2., CANVAS_HEIGHT / 2.)),
-        material: materials.add(texture2.into()),
-        transform: Transform {
-            translation: Vec3::new(
-                WINDOW_WIDTH / 2. - OFFSET - CANVAS_WIDTH / 4.,
-                WINDOW_HEIGHT / 2. - OFFSET - CANVAS_HEIGHT / 4.,
-                0.,
-            ),
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-    // Lower left
-    commands.spawn_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(CANVAS_WIDTH / 2., CANVAS_HEIGHT / 2.)),
-        material: materials.add(texture3.into()),
-        transform: Transform {
-            translation: Vec3::new(
-                WINDOW_WIDTH / 2. - OFFSET - CANVAS_WIDTH / 2. - CANVAS_WIDTH / 4.,
-                -(WINDOW_HEIGHT / 2. - OFFSET - CANVAS_HEIGHT / 4.),
-                0.,
-            ),
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-    // Lower right
-    commands.spawn_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(CANVAS_WIDTH / 2., CANVAS_HEIGHT / 2.)),
-        material: materials.add(texture4.into()),
-        transform: Transform {
-            translation: Vec3::new(
-                WINDOW_WIDTH / 2. - OFFSET - CANVAS_WIDTH / 4.,
-                -(WINDOW_HEIGHT / 2. - OFFSET - CANVAS_HEIGHT / 4.),
-                0.,
-            ),
-            ..Default::default()
-        },
-        ..Default::default()
-    });
+    for (image, material, mut visible) in gallery.iter_mut() {
+        let path = format!("{}{}.png", image_prefix, image.0);
+        if let Some(material) = materials.get_mut(material) {
+            material.texture = Some(asset_server.load(PathBuf::from(path)));
+        }
+        visible.is_visible = true;
+    }
 }
 
 pub fn infer_timer(time: Res<Time>, mut state: ResMut<State>, mut query: Query<&mut Timer>) {
@@ -317,3 +741,40 @@ pub fn infer_timer(time: Res<Time>, mut state: ResMut<State>, mut query: Query<&
         }
     }
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_models_relative_to_the_assets_folder() {
+        let dir = std::env::temp_dir().join(format!("sketch_assist_models_{}", std::process::id()));
+        let assets = dir.join("assets");
+        let models = assets.join("models");
+        let other = dir.join("other");
+        std::fs::create_dir_all(&models).unwrap();
+        std::fs::create_dir_all(&other).unwrap();
+        for file in &["b.onnx", "a.onnx", "a.labels", "notes.txt"] {
+            std::fs::write(models.join(file), b"").unwrap();
+        }
+        std::fs::write(other.join("c.onnx"), b"").unwrap();
+        let root = std::fs::canonicalize(&assets).unwrap();
+
+        assert_eq!(
+            list_models(&models, &root),
+            vec![
+                Path::new("models").join("a.onnx"),
+                Path::new("models").join("b.onnx")
+            ]
+        );
+        // Folders outside the assets folder keep their absolute path, even
+        // when given through one
+        assert_eq!(
+            list_models(&assets.join("..").join("other"), &root),
+            vec![std::fs::canonicalize(&other).unwrap().join("c.onnx")]
+        );
+        assert!(list_models(&dir.join("missing"), &root).is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
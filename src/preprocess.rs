@@ -0,0 +1,348 @@
+// Transform applied to an image before it is fed to a model. It has to match
+// the transform used during training, so every model can ship a sidecar file
+// (`cnn_sketch_3class.onnx` -> `cnn_sketch_3class.preprocess.json`) overriding
+// any of the defaults, e.g.
+//
+//     { "input_size": 28, "channels": "gray", "invert": true, "layout": "nchw" }
+//
+// The defaults reproduce the transform cnn_sketch_3class.onnx was trained with.
+// Setting `crop_to_ink` makes drawings independent of where and how large they
+// were drawn on the canvas, like the images rendered from Quick Draw strokes.
+
+use anyhow::bail;
+use image::{imageops::FilterType, Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tract_onnx::prelude::*;
+
+// Pixels with any channel darker than this are considered ink
+const INK_THRESHOLD: u8 = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channels {
+    // Only the red channel, which is enough for black ink on white
+    Red,
+    Gray,
+    Rgb,
+}
+
+impl Channels {
+    pub fn count(self) -> usize {
+        match self {
+            Channels::Red | Channels::Gray => 1,
+            Channels::Rgb => 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+// Dimension order of the input tensor
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    // No batch dimension
+    Chw,
+    Nchw,
+    Nhwc,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PreprocessConfig {
+    // Width and height of the model input
+    pub input_size: u32,
+    pub channels: Channels,
+    pub filter: ResizeFilter,
+    // Use 1 - x so that ink is bright on a dark background
+    pub invert: bool,
+    // Applied as (x - mean) / std to values in [0, 1]. Either one value per
+    // channel or a single value for all of them.
+    pub mean: Vec<f32>,
+    pub std: Vec<f32>,
+    pub layout: Layout,
+    // Crop to the bounding box of the ink and center it on a white square
+    pub crop_to_ink: bool,
+    // Space left around the ink when cropping, relative to its larger side
+    pub crop_margin: f32,
+}
+
+impl Default for PreprocessConfig {
+    fn default() -> Self {
+        PreprocessConfig {
+            input_size: 128,
+            channels: Channels::Red,
+            filter: ResizeFilter::Triangle,
+            invert: false,
+            mean: vec![0.5],
+            std: vec![0.5],
+            layout: Layout::Chw,
+            crop_to_ink: false,
+            crop_margin: 0.1,
+        }
+    }
+}
+
+impl PreprocessConfig {
+    pub fn parse(json: &str) -> anyhow::Result<Self> {
+        let config: Self = serde_json::from_str(json)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Reject settings that would produce an empty tensor or inf/NaN values
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.input_size == 0 {
+            bail!("input_size must be greater than 0");
+        }
+
+        let channels = self.channels.count();
+        for (name, values) in [("mean", &self.mean), ("std", &self.std)].iter() {
+            if values.len() != 1 && values.len() != channels {
+                bail!(
+                    "{} needs 1 or {} values, got {}",
+                    name,
+                    channels,
+                    values.len()
+                );
+            }
+        }
+        if self.std.iter().any(|&std| std == 0.0 || !std.is_finite()) {
+            bail!("std values must be finite and non-zero");
+        }
+
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn sidecar_path(model_path: &Path) -> PathBuf {
+        model_path.with_extension("preprocess.json")
+    }
+
+    pub fn apply(&self, img: &RgbImage) -> Tensor {
+        self.tensor(&self.resize(img))
+    }
+
+    // Crop if enabled and scale to `input_size`
+    pub fn resize(&self, img: &RgbImage) -> RgbImage {
+        let cropped;
+        let img = if self.crop_to_ink {
+            cropped = crop_to_ink(img, self.crop_margin);
+            &cropped
+        } else {
+            img
+        };
+
+        image::imageops::resize(img, self.input_size, self.input_size, self.filter.into())
+    }
+
+    // Convert an image of `input_size` into the model input
+    pub fn tensor(&self, resized: &RgbImage) -> Tensor {
+        let size = self.input_size as usize;
+        let channels = self.channels.count();
+
+        let value = |c: usize, y: usize, x: usize| {
+            let [r, g, b] = resized[(x as u32, y as u32)].0;
+            let v = match self.channels {
+                Channels::Red => r as f32,
+                Channels::Gray => r as f32 * 0.299 + g as f32 * 0.587 + b as f32 * 0.114,
+                Channels::Rgb => [r, g, b][c] as f32,
+            } / 255.0;
+            let v = if self.invert { 1.0 - v } else { v };
+
+            let mean = self
+                .mean
+                .get(c)
+                .or_else(|| self.mean.first())
+                .unwrap_or(&0.0);
+            let std = self.std.get(c).or_else(|| self.std.first()).unwrap_or(&1.0);
+            (v - mean) / std
+        };
+
+        match self.layout {
+            Layout::Chw => {
+                tract_ndarray::Array3::from_shape_fn((channels, size, size), |(c, y, x)| {
+                    value(c, y, x)
+                })
+                .into()
+            }
+            Layout::Nchw => {
+                tract_ndarray::Array4::from_shape_fn((1, channels, size, size), |(_, c, y, x)| {
+                    value(c, y, x)
+                })
+                .into()
+            }
+            Layout::Nhwc => {
+                tract_ndarray::Array4::from_shape_fn((1, size, size, channels), |(_, y, x, c)| {
+                    value(c, y, x)
+                })
+                .into()
+            }
+        }
+    }
+}
+
+// Cut out the bounding box of the ink, pad it to a square with `margin` times
+// its larger side on each side and center it on a white background. Images
+// without any ink are returned unchanged.
+pub fn crop_to_ink(img: &RgbImage, margin: f32) -> RgbImage {
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (x, y, pixel) in img.enumerate_pixels() {
+        if pixel.0.iter().all(|&v| v >= INK_THRESHOLD) {
+            continue;
+        }
+
+        bounds = Some(match bounds {
+            Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+            None => (x, y, x, y),
+        });
+    }
+
+    let (x0, y0, x1, y1) = match bounds {
+        Some(bounds) => bounds,
+        None => return img.clone(),
+    };
+
+    let width = x1 - x0 + 1;
+    let height = y1 - y0 + 1;
+    let side = width.max(height);
+    let padding = (side as f32 * margin.max(0.0)).round() as u32;
+    let size = side + padding * 2;
+
+    let left = (size - width) / 2;
+    let top = (size - height) / 2;
+
+    let mut square = RgbImage::from_pixel(size, size, Rgb([255, 255, 255]));
+    for y in 0..height {
+        for x in 0..width {
+            square[(left + x, top + y)] = img[(x0 + x, y0 + y)];
+        }
+    }
+
+    square
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(channels: Channels, layout: Layout) -> PreprocessConfig {
+        PreprocessConfig {
+            input_size: 28,
+            channels,
+            layout,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tensor_shapes() {
+        let img = RgbImage::from_pixel(28, 28, Rgb([255, 255, 255]));
+
+        let tensor = config(Channels::Red, Layout::Chw).tensor(&img);
+        assert_eq!(tensor.shape(), &[1, 28, 28]);
+        let tensor = config(Channels::Rgb, Layout::Nchw).tensor(&img);
+        assert_eq!(tensor.shape(), &[1, 3, 28, 28]);
+        let tensor = config(Channels::Gray, Layout::Nhwc).tensor(&img);
+        assert_eq!(tensor.shape(), &[1, 28, 28, 1]);
+    }
+
+    #[test]
+    fn tensor_values() {
+        let img = RgbImage::from_pixel(28, 28, Rgb([255, 255, 255]));
+        let tensor = config(Channels::Red, Layout::Chw).tensor(&img);
+        assert!(tensor
+            .to_array_view::<f32>()
+            .unwrap()
+            .iter()
+            .all(|&v| (v - 1.0).abs() < 1e-6));
+
+        let inverted = PreprocessConfig {
+            invert: true,
+            ..config(Channels::Red, Layout::Chw)
+        };
+        let tensor = inverted.tensor(&img);
+        assert!(tensor
+            .to_array_view::<f32>()
+            .unwrap()
+            .iter()
+            .all(|&v| (v + 1.0).abs() < 1e-6));
+    }
+
+    #[test]
+    fn parse_validates() {
+        assert_eq!(
+            PreprocessConfig::parse("{}").unwrap(),
+            PreprocessConfig::default()
+        );
+        let config = PreprocessConfig::parse(
+            r#"{"channels": "rgb", "mean": [0.5, 0.4, 0.3], "std": [0.2]}"#,
+        )
+        .unwrap();
+        assert_eq!(config.mean.len(), 3);
+
+        for json in &[
+            r#"{"input_size": 0}"#,
+            r#"{"std": [0]}"#,
+            r#"{"std": []}"#,
+            r#"{"mean": [0.5, 0.5]}"#,
+            r#"{"channels": "rgb", "std": [0.5, 0.5]}"#,
+        ] {
+            assert!(
+                PreprocessConfig::parse(json).is_err(),
+                "{} was accepted",
+                json
+            );
+        }
+    }
+
+    #[test]
+    fn crop_to_ink_centers_with_margin() {
+        // 20x10 box of ink at (10, 40)
+        let mut img = RgbImage::from_pixel(100, 100, Rgb([255, 255, 255]));
+        for y in 40..50 {
+            for x in 10..30 {
+                img[(x, y)] = Rgb([0, 0, 0]);
+            }
+        }
+
+        let cropped = crop_to_ink(&img, 0.5);
+        assert_eq!(cropped.dimensions(), (40, 40));
+        assert_eq!(cropped[(10, 15)], Rgb([0, 0, 0]));
+        assert_eq!(cropped[(29, 24)], Rgb([0, 0, 0]));
+        assert_eq!(cropped[(9, 15)], Rgb([255, 255, 255]));
+        assert_eq!(cropped[(30, 24)], Rgb([255, 255, 255]));
+        assert_eq!(cropped[(10, 14)], Rgb([255, 255, 255]));
+        assert_eq!(cropped[(10, 25)], Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn crop_to_ink_keeps_blank_images() {
+        let img = RgbImage::from_pixel(30, 20, Rgb([250, 250, 250]));
+        assert_eq!(crop_to_ink(&img, 0.1), img);
+    }
+}
@@ -0,0 +1,302 @@
+// Drawings in the ndjson format of the Quick Draw dataset, one JSON object
+// per line:
+//
+//     {"key_id":"...","word":"rabbit","countrycode":"JP","timestamp":"...",
+//      "recognized":true,"drawing":[[[x0,x1,...],[y0,y1,...],[t0,t1,...]],...]}
+//
+// Coordinates have y pointing down like `Sketch`. The raw dataset has a time
+// array in milliseconds for every stroke, the simplified one only x and y.
+//
+// The dataset also comes as pre-rendered 28x28 bitmaps in .npy files, which
+// `parse_npy_bitmaps` reads.
+
+use anyhow::{bail, Context};
+use image::{GrayImage, RgbImage, RgbaImage};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::raster;
+use crate::sketch::{Point, Sketch, Stroke, Tool};
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Drawing {
+    #[serde(default)]
+    pub key_id: String,
+    #[serde(default)]
+    pub word: String,
+    #[serde(default)]
+    pub countrycode: String,
+    #[serde(default)]
+    pub timestamp: String,
+    #[serde(default)]
+    pub recognized: bool,
+    // [xs, ys] or [xs, ys, ts] for each stroke
+    #[serde(serialize_with = "serialize_strokes")]
+    pub drawing: Vec<Vec<Vec<f64>>>,
+}
+
+impl Drawing {
+    pub fn parse(line: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(line)?)
+    }
+
+    // Every drawing of an ndjson file, skipping blank lines
+    pub fn parse_ndjson(text: &str) -> anyhow::Result<Vec<Self>> {
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| Self::parse(line).with_context(|| format!("line {}", i + 1)))
+            .collect()
+    }
+
+    pub fn to_ndjson(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    // The pen strokes of `sketch`. Eraser strokes have no equivalent in the
+    // dataset and are left out.
+    pub fn from_sketch(sketch: &Sketch) -> Self {
+        let drawing = sketch
+            .strokes
+            .iter()
+            .filter(|stroke| stroke.tool == Tool::Pen && !stroke.points.is_empty())
+            .map(|stroke| {
+                let xs = stroke.points.iter().map(|p| p.x.round() as f64).collect();
+                let ys = stroke.points.iter().map(|p| p.y.round() as f64).collect();
+                let ts = stroke.points.iter().map(|p| p.t as f64).collect();
+                vec![xs, ys, ts]
+            })
+            .collect();
+
+        Drawing {
+            drawing,
+            ..Default::default()
+        }
+    }
+
+    // Strokes in the style of `style`, scaled to fit in `width` x `height`
+    // with a margin and centered
+    pub fn to_strokes(&self, width: f32, height: f32, style: &Stroke) -> Vec<Stroke> {
+        let points = || {
+            self.drawing
+                .iter()
+                .flat_map(|stroke| stroke.get(0).into_iter().zip(stroke.get(1)))
+                .flat_map(|(xs, ys)| xs.iter().zip(ys.iter()))
+        };
+
+        let (mut x0, mut y0) = (f64::INFINITY, f64::INFINITY);
+        let (mut x1, mut y1) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for (&x, &y) in points() {
+            x0 = x0.min(x);
+            y0 = y0.min(y);
+            x1 = x1.max(x);
+            y1 = y1.max(y);
+        }
+        if x0 > x1 {
+            return Vec::new();
+        }
+
+        // Leave 10% on each side like the images the model was trained on
+        let margin = 0.1;
+        let extent = (x1 - x0).max(y1 - y0).max(1.0);
+        let scale = (width.min(height) as f64 * (1.0 - margin * 2.0)) / extent;
+        let left = (width as f64 - (x1 - x0) * scale) / 2.0;
+        let top = (height as f64 - (y1 - y0) * scale) / 2.0;
+
+        self.drawing
+            .iter()
+            .filter(|stroke| stroke.len() >= 2)
+            .map(|stroke| {
+                let ts = stroke.get(2);
+                let points = stroke[0]
+                    .iter()
+                    .zip(stroke[1].iter())
+                    .enumerate()
+                    .map(|(i, (&x, &y))| Point {
+                        x: (left + (x - x0) * scale) as f32,
+                        y: (top + (y - y0) * scale) as f32,
+                        t: ts.and_then(|ts| ts.get(i)).map_or(0, |&t| t as u32),
+                    })
+                    .collect();
+
+                Stroke {
+                    points,
+                    ..style.clone()
+                }
+            })
+            .collect()
+    }
+
+    // Draw the strokes with the default brush on a blank `width` x `height`
+    // image, as if they had been drawn on a canvas of `canvas` sketch units
+    // and rendered to a texture of that size
+    pub fn render(&self, canvas: (f32, f32), width: u32, height: u32) -> RgbImage {
+        let strokes = self.to_strokes(canvas.0, canvas.1, &Stroke::default());
+        let scale = (width as f32 / canvas.0, height as f32 / canvas.1);
+
+        let mut pixels = vec![255; (width * height * 4) as usize];
+        raster::render(&strokes, &mut pixels, width, height, scale);
+        raster::flatten(&RgbaImage::from_raw(width, height, pixels).unwrap())
+    }
+}
+
+// The dataset stores whole numbers without a fractional part
+fn serialize_strokes<S: Serializer>(
+    strokes: &[Vec<Vec<f64>>],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let strokes: Vec<Vec<Vec<serde_json::Value>>> = strokes
+        .iter()
+        .map(|stroke| {
+            stroke
+                .iter()
+                .map(|values| {
+                    values
+                        .iter()
+                        .map(|&v| {
+                            if v.fract() == 0.0 && v.abs() < i64::MAX as f64 {
+                                (v as i64).into()
+                            } else {
+                                v.into()
+                            }
+                        })
+                        .collect()
+                })
+                .collect()
+        })
+        .collect();
+
+    strokes.serialize(serializer)
+}
+
+const BITMAP_SIZE: u32 = 28;
+
+// Images of a numpy_bitmap file: an (N, 784) array of bytes holding 28x28
+// images with white ink on black
+pub fn parse_npy_bitmaps(bytes: &[u8]) -> anyhow::Result<Vec<GrayImage>> {
+    if !bytes.starts_with(b"\x93NUMPY") || bytes.len() < 10 {
+        bail!("not a .npy file");
+    }
+
+    // The header length takes 2 bytes in version 1 and 4 bytes after that
+    let (header_start, header_len) = match bytes[6] {
+        1 => (10, u16::from_le_bytes([bytes[8], bytes[9]]) as usize),
+        _ if bytes.len() >= 12 => (
+            12,
+            u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+        ),
+        _ => bail!("truncated .npy header"),
+    };
+    let data = bytes
+        .get(header_start + header_len..)
+        .context("truncated .npy header")?;
+    let header = std::str::from_utf8(&bytes[header_start..header_start + header_len])
+        .context("invalid .npy header")?;
+
+    if !header.contains("u1") || header.contains("'fortran_order': True") {
+        bail!("expected a C ordered array of bytes, got {}", header.trim());
+    }
+
+    let pixels = (BITMAP_SIZE * BITMAP_SIZE) as usize;
+    if data.len() % pixels != 0 {
+        bail!(
+            "the array does not hold {}x{} images",
+            BITMAP_SIZE,
+            BITMAP_SIZE
+        );
+    }
+
+    Ok(data
+        .chunks_exact(pixels)
+        .map(|bitmap| GrayImage::from_raw(BITMAP_SIZE, BITMAP_SIZE, bitmap.to_vec()).unwrap())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE: &str = r#"{"key_id":"1","word":"axe","countrycode":"JP","timestamp":"t","recognized":true,"drawing":[[[0,10],[5,15]],[[3],[4],[0]]]}"#;
+
+    #[test]
+    fn ndjson_round_trip() {
+        let drawing = Drawing::parse(LINE).unwrap();
+        assert_eq!(drawing.word, "axe");
+        assert_eq!(drawing.drawing[0], vec![vec![0.0, 10.0], vec![5.0, 15.0]]);
+        assert_eq!(drawing.to_ndjson(), LINE);
+
+        let text = format!("{}\n\n{}\n", LINE, LINE);
+        assert_eq!(Drawing::parse_ndjson(&text).unwrap().len(), 2);
+        let err = Drawing::parse_ndjson("{}\nnot json").unwrap_err();
+        assert!(format!("{:#}", err).starts_with("line 1"));
+    }
+
+    #[test]
+    fn from_sketch_leaves_out_eraser_strokes() {
+        let mut sketch = Sketch::default();
+        sketch.push_point(1.4, 2.6, 0.0);
+        sketch.end_stroke();
+        sketch.begin_stroke(Stroke::eraser(5.0));
+        sketch.push_point(1.0, 1.0, 0.0);
+        sketch.end_stroke();
+
+        let drawing = Drawing::from_sketch(&sketch);
+        assert_eq!(drawing.drawing, vec![vec![vec![1.0], vec![3.0], vec![0.0]]]);
+    }
+
+    #[test]
+    fn render_fits_the_image() {
+        let drawing = Drawing::parse(LINE).unwrap();
+        let img = drawing.render((600.0, 300.0), 200, 100);
+
+        assert_eq!(img.dimensions(), (200, 100));
+        assert!(img.pixels().any(|pixel| pixel.0[0] < 128));
+        // Centered with a margin
+        assert_eq!(img[(0, 0)].0, [255, 255, 255]);
+        assert_eq!(img[(199, 99)].0, [255, 255, 255]);
+    }
+
+    fn npy(version: u8, header: &str, data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"\x93NUMPY".to_vec();
+        bytes.extend_from_slice(&[version, 0]);
+        if version == 1 {
+            bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        } else {
+            bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        }
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    const HEADER: &str = "{'descr': '|u1', 'fortran_order': False, 'shape': (2, 784), }\n";
+
+    #[test]
+    fn npy_bitmaps() {
+        let mut data = vec![0; 784 * 2];
+        data[784 + 29] = 255;
+
+        for &version in &[1, 2] {
+            let bitmaps = parse_npy_bitmaps(&npy(version, HEADER, &data)).unwrap();
+            assert_eq!(bitmaps.len(), 2);
+            assert_eq!(bitmaps[1].dimensions(), (28, 28));
+            assert_eq!(bitmaps[1][(1, 1)].0, [255]);
+            assert_eq!(bitmaps[0][(1, 1)].0, [0]);
+        }
+    }
+
+    #[test]
+    fn npy_errors() {
+        assert!(parse_npy_bitmaps(b"PNG").is_err());
+
+        // Header longer than the file
+        let mut truncated = npy(1, HEADER, &[]);
+        truncated.truncate(20);
+        assert!(parse_npy_bitmaps(&truncated).is_err());
+        assert!(parse_npy_bitmaps(&b"\x93NUMPY\x02\x00\x01\x00"[..]).is_err());
+
+        // Not a whole number of images
+        assert!(parse_npy_bitmaps(&npy(1, HEADER, &[0; 100])).is_err());
+        let floats = HEADER.replace("|u1", "<f4");
+        assert!(parse_npy_bitmaps(&npy(1, &floats, &[0; 784])).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+// Anti-aliased rasterization of strokes onto an RGBA8 pixel buffer.
+//
+// A stroke is painted as a chain of thick segments with round caps, which
+// also gives round joins. Each pixel gets the coverage of the nearest part of
+// the stroke, estimated from the distance between its center and the segment.
+// Coverage is accumulated with max() over the whole stroke and composited over
+// the pixels as they were before the stroke started, so translucent strokes
+// do not get darker where they overlap themselves.
+//
+// Images loaded onto the canvas are fitted to it here as well.
+
+use crate::sketch::Stroke;
+use image::{imageops::FilterType, Rgb, RgbImage, RgbaImage};
+
+#[derive(Default)]
+pub struct StrokeRasterizer {
+    width: u32,
+    height: u32,
+    // RGB of the pixels before the current stroke started
+    base: Vec<u8>,
+    // Coverage of the current stroke in [0, 1], one value per pixel
+    coverage: Vec<f32>,
+    active: bool,
+}
+
+impl StrokeRasterizer {
+    // Start a new stroke on `pixels`, a `width` x `height` RGBA8 buffer
+    pub fn begin(&mut self, pixels: &[u8], width: u32, height: u32) {
+        let len = (width * height) as usize;
+        assert_eq!(
+            pixels.len(),
+            len * 4,
+            "not a {}x{} RGBA8 buffer",
+            width,
+            height
+        );
+
+        self.width = width;
+        self.height = height;
+        self.base.clear();
+        for pixel in pixels.chunks_exact(4) {
+            self.base.extend_from_slice(&pixel[..3]);
+        }
+        self.coverage.clear();
+        self.coverage.resize(len, 0.0);
+        self.active = true;
+    }
+
+    pub fn end(&mut self) {
+        self.active = false;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // Paint the segment from `from` to `to` in pixel coordinates (y pointing
+    // down). A segment with equal ends paints a dot.
+    pub fn segment(
+        &mut self,
+        pixels: &mut [u8],
+        from: (f32, f32),
+        to: (f32, f32),
+        radius: f32,
+        color: [u8; 4],
+    ) {
+        if !self.active {
+            return;
+        }
+
+        // Half a pixel of falloff on each side of the edge
+        let reach = radius + 0.5;
+        let x0 = (from.0.min(to.0) - reach).floor().max(0.0) as u32;
+        let y0 = (from.1.min(to.1) - reach).floor().max(0.0) as u32;
+        let x1 = ((from.0.max(to.0) + reach).ceil().max(0.0) as u32).min(self.width);
+        let y1 = ((from.1.max(to.1) + reach).ceil().max(0.0) as u32).min(self.height);
+
+        let alpha = color[3] as f32 / 255.0;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let center = (x as f32 + 0.5, y as f32 + 0.5);
+                let coverage = (reach - distance_to_segment(center, from, to)).min(1.0);
+
+                let index = (y * self.width + x) as usize;
+                if coverage <= self.coverage[index] {
+                    continue;
+                }
+                self.coverage[index] = coverage;
+
+                let weight = alpha * coverage;
+                for (c, &channel) in color[..3].iter().enumerate() {
+                    let base = self.base[index * 3 + c] as f32;
+                    let value = base + (channel as f32 - base) * weight;
+                    pixels[index * 4 + c] = value.round() as u8;
+                }
+            }
+        }
+    }
+}
+
+// Paint all of `strokes` onto `pixels`, scaling stroke coordinates by `scale`
+pub fn render(strokes: &[Stroke], pixels: &mut [u8], width: u32, height: u32, scale: (f32, f32)) {
+    let mut rasterizer = StrokeRasterizer::default();
+    for stroke in strokes {
+        rasterizer.begin(pixels, width, height);
+        let radius = stroke.radius * (scale.0 + scale.1) / 2.0;
+
+        let points: Vec<_> = stroke
+            .points
+            .iter()
+            .map(|p| (p.x * scale.0, p.y * scale.1))
+            .collect();
+        match points.as_slice() {
+            [] => {}
+            [dot] => rasterizer.segment(pixels, *dot, *dot, radius, stroke.color),
+            _ => {
+                for pair in points.windows(2) {
+                    rasterizer.segment(pixels, pair[0], pair[1], radius, stroke.color);
+                }
+            }
+        }
+
+        rasterizer.end();
+    }
+}
+
+fn distance_to_segment(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared > 0.0 {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length_squared)
+            .max(0.0)
+            .min(1.0)
+    } else {
+        0.0
+    };
+
+    let (x, y) = (a.0 + t * dx - p.0, a.1 + t * dy - p.1);
+    (x * x + y * y).sqrt()
+}
+
+// Scale `img` to fit in `width` x `height` keeping its aspect ratio, centered
+// on white. Transparent parts of the image are composited on white as well.
+pub fn fit(img: &RgbaImage, width: u32, height: u32) -> RgbImage {
+    let scale = (width as f32 / img.width() as f32).min(height as f32 / img.height() as f32);
+    let scaled_width = ((img.width() as f32 * scale).round() as u32)
+        .max(1)
+        .min(width);
+    let scaled_height = ((img.height() as f32 * scale).round() as u32)
+        .max(1)
+        .min(height);
+    let scaled = image::imageops::resize(img, scaled_width, scaled_height, FilterType::Triangle);
+
+    let left = (width - scaled_width) / 2;
+    let top = (height - scaled_height) / 2;
+
+    let mut fitted = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+    image::imageops::overlay(&mut fitted, &flatten(&scaled), left, top);
+    fitted
+}
+
+// Composite `img` on white
+pub fn flatten(img: &RgbaImage) -> RgbImage {
+    RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let [r, g, b, a] = img[(x, y)].0;
+        let alpha = a as f32 / 255.0;
+        let blend = |value: u8| (255.0 + (value as f32 - 255.0) * alpha).round() as u8;
+        Rgb([blend(r), blend(g), blend(b)])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 20;
+
+    fn white() -> Vec<u8> {
+        vec![255; (SIZE * SIZE * 4) as usize]
+    }
+
+    fn red(pixels: &[u8], x: u32, y: u32) -> u8 {
+        pixels[((y * SIZE + x) * 4) as usize]
+    }
+
+    #[test]
+    fn segment_coverage() {
+        let mut pixels = white();
+        let mut rasterizer = StrokeRasterizer::default();
+        rasterizer.begin(&pixels, SIZE, SIZE);
+        // Along the centers of a row of pixels
+        rasterizer.segment(&mut pixels, (5.5, 10.5), (15.5, 10.5), 2.0, [0, 0, 0, 255]);
+
+        // Fully covered within the radius, untouched beyond it
+        assert_eq!(red(&pixels, 10, 10), 0);
+        assert_eq!(red(&pixels, 10, 11), 0);
+        assert_eq!(red(&pixels, 10, 13), 255);
+        assert_eq!(red(&pixels, 2, 10), 255);
+        // Half covered half a pixel from the edge
+        let edge = red(&pixels, 10, 12);
+        assert!(edge > 0 && edge < 255, "edge is {}", edge);
+    }
+
+    #[test]
+    fn no_darkening_where_a_stroke_overlaps_itself() {
+        let color = [0, 0, 0, 128];
+        let mut pixels = white();
+        let mut rasterizer = StrokeRasterizer::default();
+        rasterizer.begin(&pixels, SIZE, SIZE);
+        rasterizer.segment(&mut pixels, (5.0, 10.0), (15.0, 10.0), 2.0, color);
+        let once = red(&pixels, 10, 10);
+        rasterizer.segment(&mut pixels, (15.0, 10.0), (5.0, 10.0), 2.0, color);
+        assert_eq!(red(&pixels, 10, 10), once);
+        rasterizer.end();
+
+        // A new stroke is composited over the previous one
+        rasterizer.begin(&pixels, SIZE, SIZE);
+        rasterizer.segment(&mut pixels, (5.0, 10.0), (15.0, 10.0), 2.0, color);
+        assert!(red(&pixels, 10, 10) < once);
+    }
+
+    #[test]
+    fn inactive_rasterizer_paints_nothing() {
+        let mut pixels = white();
+        let mut rasterizer = StrokeRasterizer::default();
+        rasterizer.segment(&mut pixels, (5.0, 10.0), (15.0, 10.0), 2.0, [0, 0, 0, 255]);
+        assert_eq!(pixels, white());
+    }
+}
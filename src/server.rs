@@ -0,0 +1,191 @@
+// Local HTTP server classifying sketches for front-ends that do not ship the
+// wasm build. Requests are served one at a time.
+//
+//     GET  /health              {"status":"ok","model":...,"labels":[...],...}
+//     POST /classify?top=K      PNG/JPEG body, or a Quick Draw drawing as JSON
+//                               {"predictions":[{"class":0,"label":...},...]}
+
+use anyhow::Context;
+use serde::Serialize;
+use sketch_assist::{
+    classifier::{LabeledPrediction, SketchClassifier},
+    preprocess::PreprocessConfig,
+    quickdraw::Drawing,
+    raster,
+};
+use std::io::Read;
+use std::path::Path;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::draw::{CANVAS_HEIGHT, CANVAS_WIDTH, TEXTURE_SIZE};
+
+// Larger bodies are rejected
+const MAX_BODY_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Serialize)]
+struct Health<'a> {
+    status: &'a str,
+    model: &'a Path,
+    labels: Vec<String>,
+    preprocess: &'a PreprocessConfig,
+}
+
+#[derive(Serialize)]
+struct Classification {
+    predictions: Vec<LabeledPrediction>,
+}
+
+#[derive(Serialize)]
+struct Error {
+    error: String,
+}
+
+pub fn serve(
+    classifier: &SketchClassifier,
+    model_path: &Path,
+    port: u16,
+    top: usize,
+) -> anyhow::Result<()> {
+    let address = format!("127.0.0.1:{}", port);
+    let server = Server::http(&address)
+        .map_err(|err| anyhow::anyhow!(err))
+        .with_context(|| format!("could not listen on {}", address))?;
+    println!("Serving {} on http://{}", model_path.display(), address);
+
+    for mut request in server.incoming_requests() {
+        let (path, query) = match request.url().split_once('?') {
+            Some((path, query)) => (path.to_string(), query.to_string()),
+            None => (request.url().to_string(), String::new()),
+        };
+
+        let method = request.method().clone();
+        let response = match (&method, path.as_str()) {
+            // Preflight of cross-origin POSTs with a JSON body
+            (Method::Options, _) => Response::from_data(Vec::new()).with_status_code(204),
+            (Method::Get, "/health") => json(
+                200,
+                &Health {
+                    status: "ok",
+                    model: model_path,
+                    labels: classifier
+                        .labels()
+                        .iter()
+                        .map(|label| label.name.clone())
+                        .collect(),
+                    preprocess: classifier.preprocess(),
+                },
+            ),
+            (Method::Post, "/classify") => match classify(classifier, &mut request, &query, top) {
+                Ok(predictions) => json(200, &Classification { predictions }),
+                Err(err) => json(
+                    400,
+                    &Error {
+                        error: format!("{:#}", err),
+                    },
+                ),
+            },
+            (_, "/health") | (_, "/classify") => json(
+                405,
+                &Error {
+                    error: "method not allowed".to_string(),
+                },
+            ),
+            _ => json(
+                404,
+                &Error {
+                    error: "not found".to_string(),
+                },
+            ),
+        };
+
+        let response = response
+            .with_header(header("Access-Control-Allow-Origin", "*"))
+            .with_header(header("Access-Control-Allow-Methods", "GET, POST, OPTIONS"))
+            .with_header(header("Access-Control-Allow-Headers", "Content-Type"));
+        if let Err(err) = request.respond(response) {
+            eprintln!("error: could not respond: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+fn classify(
+    classifier: &SketchClassifier,
+    request: &mut Request,
+    query: &str,
+    default_top: usize,
+) -> anyhow::Result<Vec<LabeledPrediction>> {
+    let top = parse_top(query, default_top)?;
+
+    let is_json = request
+        .headers()
+        .iter()
+        .any(|header| header.field.equiv("Content-Type") && header.value.as_str().contains("json"));
+
+    let body = read_body(request.as_reader(), MAX_BODY_SIZE)?;
+
+    let img = if is_json {
+        let drawing =
+            Drawing::parse(std::str::from_utf8(&body)?).context("not a Quick Draw drawing")?;
+        drawing.render((CANVAS_WIDTH, CANVAS_HEIGHT), TEXTURE_SIZE, TEXTURE_SIZE)
+    } else {
+        let img = image::load_from_memory(&body).context("not an image")?;
+        raster::flatten(&img.to_rgba8())
+    };
+
+    classifier.classify_top(&img, top)
+}
+
+// The top=K parameter of the query string, if there is one
+fn parse_top(query: &str, default_top: usize) -> anyhow::Result<usize> {
+    match query.split('&').find_map(|pair| pair.strip_prefix("top=")) {
+        Some(top) => top.parse().context("top needs a number"),
+        None => Ok(default_top),
+    }
+}
+
+// Read at most `max_size` bytes, failing on larger bodies
+fn read_body<R: Read>(reader: R, max_size: u64) -> anyhow::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    reader
+        .take(max_size + 1)
+        .read_to_end(&mut body)
+        .context("could not read the body")?;
+    if body.len() as u64 > max_size {
+        anyhow::bail!("the body is larger than {} bytes", max_size);
+    }
+    Ok(body)
+}
+
+fn json<T: Serialize>(status: u16, body: &T) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_data(serde_json::to_vec(body).unwrap())
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn top_parameter() {
+        assert_eq!(parse_top("", 5).unwrap(), 5);
+        assert_eq!(parse_top("top=2", 5).unwrap(), 2);
+        assert_eq!(parse_top("format=json&top=10", 5).unwrap(), 10);
+        assert_eq!(parse_top("stop=1", 5).unwrap(), 5);
+        assert!(parse_top("top=", 5).is_err());
+        assert!(parse_top("top=-1", 5).is_err());
+    }
+
+    #[test]
+    fn body_size() {
+        assert_eq!(read_body(&b"1234"[..], 4).unwrap(), b"1234");
+        assert_eq!(read_body(&b""[..], 4).unwrap(), b"");
+        assert!(read_body(&b"12345"[..], 4).is_err());
+    }
+}
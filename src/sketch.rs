@@ -0,0 +1,238 @@
+// Vector representation of what the user has drawn on the canvas.
+//
+// Points are stored in canvas coordinates with the origin at the top left
+// corner (x to the right, y downwards), the same convention Quick Draw uses.
+// Timestamps are milliseconds since the first point of the sketch.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+    pub t: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tool {
+    Pen,
+    // Paints the white background back
+    Eraser,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stroke {
+    pub points: Vec<Point>,
+    pub tool: Tool,
+    // Brush radius in canvas pixels
+    pub radius: f32,
+    // sRGB color with the opacity in alpha
+    pub color: [u8; 4],
+}
+
+impl Stroke {
+    pub fn new(radius: f32, color: [u8; 4]) -> Self {
+        Stroke {
+            points: Vec::new(),
+            tool: Tool::Pen,
+            radius,
+            color,
+        }
+    }
+
+    pub fn eraser(radius: f32) -> Self {
+        Stroke {
+            points: Vec::new(),
+            tool: Tool::Eraser,
+            radius,
+            color: [255, 255, 255, 255],
+        }
+    }
+}
+
+impl Default for Stroke {
+    fn default() -> Self {
+        Stroke::new(2.0, [0, 0, 0, 255])
+    }
+}
+
+#[derive(Default)]
+pub struct Sketch {
+    pub strokes: Vec<Stroke>,
+    // Strokes taken back by undo, most recent last
+    undone: Vec<Stroke>,
+    // Time in seconds at which the first point was recorded
+    started_at: Option<f64>,
+    drawing: bool,
+}
+
+impl Sketch {
+    pub fn is_drawing(&self) -> bool {
+        self.drawing
+    }
+
+    // The stroke being drawn, if any
+    pub fn current_stroke(&self) -> Option<&Stroke> {
+        if self.drawing {
+            self.strokes.last()
+        } else {
+            None
+        }
+    }
+
+    // Start a new stroke drawn with the style of `stroke`
+    pub fn begin_stroke(&mut self, stroke: Stroke) {
+        self.strokes.push(stroke);
+        self.undone.clear();
+        self.drawing = true;
+    }
+
+    // Append a point to the stroke being drawn, starting one with the default
+    // style if necessary. `now` is the current time in seconds.
+    pub fn push_point(&mut self, x: f32, y: f32, now: f64) {
+        if !self.drawing {
+            self.begin_stroke(Stroke::default());
+        }
+
+        let started_at = *self.started_at.get_or_insert(now);
+        let t = ((now - started_at) * 1000.0).max(0.0) as u32;
+
+        self.strokes
+            .last_mut()
+            .unwrap()
+            .points
+            .push(Point { x, y, t });
+    }
+
+    // Returns true if a stroke was in progress
+    pub fn end_stroke(&mut self) -> bool {
+        let was_drawing = self.drawing;
+        self.drawing = false;
+        was_drawing
+    }
+
+    // Drop the stroke being drawn without a way to redo it, e.g. when it turns
+    // out to be part of a gesture. Returns true if a stroke was in progress.
+    pub fn cancel_stroke(&mut self) -> bool {
+        if !self.drawing {
+            return false;
+        }
+
+        self.drawing = false;
+        self.strokes.pop();
+        true
+    }
+
+    // Returns true if a stroke was removed
+    pub fn undo(&mut self) -> bool {
+        if self.drawing {
+            return false;
+        }
+
+        match self.strokes.pop() {
+            Some(stroke) => {
+                self.undone.push(stroke);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Returns true if a stroke was restored
+    pub fn redo(&mut self) -> bool {
+        if self.drawing {
+            return false;
+        }
+
+        match self.undone.pop() {
+            Some(stroke) => {
+                self.strokes.push(stroke);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.strokes.clear();
+        self.undone.clear();
+        self.started_at = None;
+        self.drawing = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stroke(sketch: &mut Sketch, x: f32) {
+        sketch.push_point(x, 0.0, 0.0);
+        sketch.end_stroke();
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut sketch = Sketch::default();
+        stroke(&mut sketch, 1.0);
+        stroke(&mut sketch, 2.0);
+
+        assert!(sketch.undo());
+        assert_eq!(sketch.strokes.len(), 1);
+        assert!(sketch.redo());
+        assert_eq!(sketch.strokes[1].points[0].x, 2.0);
+        assert!(!sketch.redo());
+
+        assert!(sketch.undo());
+        assert!(sketch.undo());
+        assert!(!sketch.undo());
+        assert!(sketch.strokes.is_empty());
+    }
+
+    #[test]
+    fn new_stroke_drops_redo() {
+        let mut sketch = Sketch::default();
+        stroke(&mut sketch, 1.0);
+        sketch.undo();
+        stroke(&mut sketch, 2.0);
+
+        assert!(!sketch.redo());
+        assert_eq!(sketch.strokes.len(), 1);
+    }
+
+    #[test]
+    fn no_undo_while_drawing() {
+        let mut sketch = Sketch::default();
+        sketch.push_point(1.0, 1.0, 0.0);
+
+        assert!(!sketch.undo());
+        assert!(sketch.end_stroke());
+        assert!(sketch.undo());
+    }
+
+    #[test]
+    fn cancel_stroke() {
+        let mut sketch = Sketch::default();
+        stroke(&mut sketch, 1.0);
+        assert!(!sketch.cancel_stroke());
+
+        sketch.push_point(2.0, 0.0, 0.0);
+        assert!(sketch.cancel_stroke());
+        assert!(!sketch.is_drawing());
+        assert_eq!(sketch.strokes.len(), 1);
+        assert!(!sketch.redo());
+    }
+
+    #[test]
+    fn clear_drops_everything() {
+        let mut sketch = Sketch::default();
+        stroke(&mut sketch, 1.0);
+        stroke(&mut sketch, 2.0);
+        sketch.undo();
+        sketch.clear();
+
+        assert!(sketch.strokes.is_empty());
+        assert!(!sketch.redo());
+
+        // Timestamps start over
+        sketch.push_point(0.0, 0.0, 10.0);
+        assert_eq!(sketch.strokes[0].points[0].t, 0);
+    }
+}
@@ -0,0 +1,82 @@
+// Smoothing of pointer input while a stroke is drawn.
+//
+// Every raw position pulls the smoothed position part of the way towards it
+// (an exponential moving average), which filters out hand and mouse jitter at
+// the cost of some lag. The lag is made up for when the stroke ends so that
+// strokes still finish where the pointer was released.
+
+#[derive(Clone, Debug, Default)]
+pub struct Stabilizer {
+    // 0 disables smoothing, values close to 1 smooth heavily
+    pub strength: f32,
+    smoothed: Option<(f32, f32)>,
+    raw: Option<(f32, f32)>,
+}
+
+impl Stabilizer {
+    pub fn new(strength: f32) -> Self {
+        Stabilizer {
+            strength,
+            ..Default::default()
+        }
+    }
+
+    // Forget the current stroke
+    pub fn reset(&mut self) {
+        self.smoothed = None;
+        self.raw = None;
+    }
+
+    // Feed a raw position and get the position to draw
+    pub fn push(&mut self, x: f32, y: f32) -> (f32, f32) {
+        let strength = self.strength.max(0.0).min(0.99);
+        let smoothed = match self.smoothed {
+            Some((sx, sy)) => (
+                sx + (x - sx) * (1.0 - strength),
+                sy + (y - sy) * (1.0 - strength),
+            ),
+            None => (x, y),
+        };
+
+        self.smoothed = Some(smoothed);
+        self.raw = Some((x, y));
+        smoothed
+    }
+
+    // The last raw position, if the smoothed stroke has not reached it yet.
+    // Resets the stabilizer for the next stroke.
+    pub fn finish(&mut self) -> Option<(f32, f32)> {
+        let end = match (self.smoothed, self.raw) {
+            (Some(smoothed), Some(raw)) if smoothed != raw => Some(raw),
+            _ => None,
+        };
+
+        self.reset();
+        end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finish_returns_the_raw_end() {
+        let mut stabilizer = Stabilizer::new(0.5);
+        assert_eq!(stabilizer.push(0.0, 0.0), (0.0, 0.0));
+        assert_eq!(stabilizer.push(10.0, 0.0), (5.0, 0.0));
+        assert_eq!(stabilizer.finish(), Some((10.0, 0.0)));
+
+        // The next stroke starts where it is drawn
+        assert_eq!(stabilizer.push(20.0, 20.0), (20.0, 20.0));
+    }
+
+    #[test]
+    fn finish_without_lag() {
+        let mut stabilizer = Stabilizer::new(0.0);
+        stabilizer.push(0.0, 0.0);
+        assert_eq!(stabilizer.push(10.0, 5.0), (10.0, 5.0));
+        assert_eq!(stabilizer.finish(), None);
+        assert_eq!(stabilizer.finish(), None);
+    }
+}
@@ -0,0 +1,84 @@
+// Vector export of sketches. Each stroke becomes a path with round caps and
+// joins, drawn with the width, color and opacity of its brush, so the SVG
+// looks like the canvas at any resolution.
+
+use std::fmt::Write;
+
+use crate::sketch::Sketch;
+
+// `width` and `height` are the size of the canvas in sketch coordinates
+pub fn to_svg(sketch: &Sketch, width: f32, height: f32) -> String {
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = width,
+        h = height
+    )
+    .unwrap();
+    writeln!(svg, r#"  <rect width="100%" height="100%" fill="white"/>"#).unwrap();
+
+    for stroke in sketch.strokes.iter() {
+        let (first, rest) = match stroke.points.split_first() {
+            Some(points) => points,
+            None => continue,
+        };
+
+        let mut d = format!("M{:.1} {:.1}", first.x, first.y);
+        // A zero length segment still gets its round caps, which draws a dot
+        if rest.is_empty() {
+            write!(d, " L{:.1} {:.1}", first.x, first.y).unwrap();
+        }
+        for point in rest {
+            write!(d, " L{:.1} {:.1}", point.x, point.y).unwrap();
+        }
+
+        let [r, g, b, a] = stroke.color;
+        writeln!(
+            svg,
+            r##"  <path d="{}" fill="none" stroke="#{:02x}{:02x}{:02x}" stroke-opacity="{:.3}" stroke-width="{:.1}" stroke-linecap="round" stroke-linejoin="round"/>"##,
+            d,
+            r,
+            g,
+            b,
+            a as f32 / 255.0,
+            stroke.radius * 2.0
+        )
+        .unwrap();
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sketch::Stroke;
+
+    #[test]
+    fn paths() {
+        let mut sketch = Sketch::default();
+        sketch.begin_stroke(Stroke::new(1.5, [255, 0, 16, 128]));
+        sketch.push_point(1.0, 2.0, 0.0);
+        sketch.push_point(3.3, 4.0, 0.0);
+        sketch.end_stroke();
+        // A single point becomes a dot
+        sketch.push_point(5.0, 6.0, 0.0);
+        sketch.end_stroke();
+        // Empty strokes are left out
+        sketch.begin_stroke(Stroke::default());
+        sketch.end_stroke();
+
+        let svg = to_svg(&sketch, 600.0, 400.0);
+        assert!(svg.starts_with(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="600" height="400" viewBox="0 0 600 400">"#
+        ));
+        assert!(svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<path").count(), 2);
+        assert!(svg.contains(
+            r##"d="M1.0 2.0 L3.3 4.0" fill="none" stroke="#ff0010" stroke-opacity="0.502" stroke-width="3.0""##
+        ));
+        assert!(svg.contains(r#"d="M5.0 6.0 L5.0 6.0""#));
+    }
+}